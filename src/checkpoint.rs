@@ -0,0 +1,75 @@
+use crate::physics::{ColliderType, CollisionShape, Collisions, Hitbox, Position};
+use crate::player::{Player, SpawnPoint};
+use bevy::prelude::*;
+
+// volume that moves the spawn point to `spawn` once the player touches it
+#[derive(Component)]
+pub struct Checkpoint {
+    pub spawn: Vec2,
+    pub activated: bool,
+}
+
+pub struct CheckpointMaterials {
+    pub inactive: Handle<ColorMaterial>,
+    pub active: Handle<ColorMaterial>,
+}
+
+pub fn spawn_checkpoints(
+    mut commands: Commands,
+    mut material_assets: ResMut<Assets<ColorMaterial>>,
+) {
+    let materials = CheckpointMaterials {
+        inactive: material_assets.add(Color::rgb(0.4, 0.4, 0.6).into()),
+        active: material_assets.add(Color::rgb(0.3, 0.8, 0.4).into()),
+    };
+
+    let size = Vec2::new(16.0, 40.0);
+    // the spawn points sit on the ground under each checkpoint
+    for &(position, spawn) in [
+        (Vec2::new(-60.0, 20.0), Vec2::new(-60.0, 15.0)),
+        (Vec2::new(110.0, 80.0), Vec2::new(110.0, 75.0)),
+    ]
+    .iter()
+    {
+        commands
+            .spawn()
+            .insert_bundle(SpriteBundle {
+                material: materials.inactive.clone(),
+                sprite: Sprite::new(size),
+                transform: Transform::from_translation(position.extend(-1.0)),
+                ..Default::default()
+            })
+            .insert(Checkpoint {
+                spawn,
+                activated: false,
+            })
+            .insert(Position(position))
+            .insert(Hitbox {
+                shape: CollisionShape::Rect(size),
+                col_type: ColliderType::Checkpoint,
+            });
+    }
+
+    commands.insert_resource(materials);
+}
+
+// the last checkpoint touched is the one the player respawns at, even if it was activated before
+pub fn activate_checkpoints(
+    player_q: Query<&Collisions, With<Player>>,
+    mut checkpoints_q: Query<(&mut Checkpoint, &mut Handle<ColorMaterial>)>,
+    materials: Res<CheckpointMaterials>,
+    mut spawn_point: ResMut<SpawnPoint>,
+) {
+    for cs in player_q.iter() {
+        for collision_data in cs.0.iter() {
+            if let Ok((mut checkpoint, mut material)) = checkpoints_q.get_mut(collision_data.entity)
+            {
+                spawn_point.0 = checkpoint.spawn;
+                if !checkpoint.activated {
+                    checkpoint.activated = true;
+                    *material = materials.active.clone();
+                }
+            }
+        }
+    }
+}
@@ -1,9 +1,78 @@
-use crate::physics::{ColliderType, CollisionShape, Hitbox, Position};
+use crate::loader::NeedToLoad;
+use crate::physics::{
+    ColliderType, CollisionShape, Displacement, Hitbox, PhysicsTimestep, Position,
+    PreviousPosition, Velocity,
+};
 use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
 
 #[derive(Component)]
 pub struct Ground;
 
+#[derive(serde::Deserialize, Clone, Copy)]
+pub enum PathMode {
+    // back and forth along the waypoints
+    PingPong,
+    // from the last waypoint straight back to the first
+    Loop,
+}
+
+#[derive(serde::Deserialize)]
+pub struct MovingPlatformDef {
+    pub size: Vec2,
+    pub speed: f32,
+    pub mode: PathMode,
+    pub waypoints: Vec<Vec2>,
+}
+
+#[derive(serde::Deserialize, TypeUuid)]
+#[uuid = "9d2c6f1e-4b7a-4e3d-8c51-6a0f2e9b7d34"]
+pub struct MovingPlatforms {
+    pub platforms: Vec<MovingPlatformDef>,
+}
+
+#[derive(Default)]
+pub struct MovingPlatformsHandle(pub Handle<MovingPlatforms>);
+
+#[derive(Component)]
+pub struct MovingPlatform {
+    pub speed: f32,
+    pub mode: PathMode,
+    pub waypoints: Vec<Vec2>,
+    // index of the waypoint being moved towards
+    pub target: usize,
+    // only used by ping pong paths
+    pub forward: bool,
+}
+
+impl MovingPlatform {
+    // heads back to the first waypoint as if just spawned, returning where to put the platform
+    pub fn rewind(self: &mut Self) -> Vec2 {
+        self.target = 0;
+        self.forward = true;
+        self.waypoints[0]
+    }
+
+    fn advance_target(self: &mut Self) {
+        let last = self.waypoints.len() - 1;
+        match self.mode {
+            PathMode::Loop => self.target = (self.target + 1) % self.waypoints.len(),
+            PathMode::PingPong => {
+                if self.forward && self.target == last {
+                    self.forward = false;
+                } else if !self.forward && self.target == 0 {
+                    self.forward = true;
+                }
+                self.target = if self.forward {
+                    (self.target + 1).min(last)
+                } else {
+                    self.target.saturating_sub(1)
+                };
+            }
+        }
+    }
+}
+
 pub fn spawn_ground(mut commands: Commands, mut material_assets: ResMut<Assets<ColorMaterial>>) {
     commands
         .spawn()
@@ -21,4 +90,165 @@ pub fn spawn_ground(mut commands: Commands, mut material_assets: ResMut<Assets<C
             shape: CollisionShape::Rect(Vec2::new(240.0, 60.0)),
             col_type: ColliderType::Ground,
         });
+
+    // ramp up to the right edge of the ground
+    let ramp_size = Vec2::new(120.0, 60.0);
+    let ramp_position = Vec2::new(60.0, 30.0);
+    commands
+        .spawn()
+        .insert_bundle(SpriteBundle {
+            material: material_assets
+                .add(Color::rgb(0.3, 0.3, 0.3).into())
+                .clone(),
+            // only the surface is drawn, as a thin strip along the hypotenuse
+            sprite: Sprite::new(Vec2::new(ramp_size.length(), 4.0)),
+            transform: Transform {
+                translation: ramp_position.extend(0.0),
+                rotation: Quat::from_rotation_z(ramp_size.y.atan2(ramp_size.x)),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Ground)
+        .insert(Position(ramp_position))
+        .insert(Hitbox {
+            shape: CollisionShape::Slope {
+                size: ramp_size,
+                rises_right: true,
+            },
+            col_type: ColliderType::Ground,
+        });
+
+    // platform over the left side that can be jumped up through
+    commands
+        .spawn()
+        .insert_bundle(SpriteBundle {
+            material: material_assets
+                .add(Color::rgb(0.5, 0.5, 0.3).into())
+                .clone(),
+            sprite: Sprite::new(Vec2::new(80.0, 8.0)),
+            transform: Transform::from_translation(Vec3::new(-60.0, 70.0, 0.0)),
+            ..Default::default()
+        })
+        .insert(Ground)
+        .insert(Position(Vec2::new(-60.0, 70.0)))
+        .insert(Hitbox {
+            shape: CollisionShape::Rect(Vec2::new(80.0, 8.0)),
+            col_type: ColliderType::OneWayGround,
+        });
+}
+
+// spikes on the ground and a kill plane under the whole level to catch anything that falls off
+pub fn spawn_hazards(mut commands: Commands, mut material_assets: ResMut<Assets<ColorMaterial>>) {
+    commands
+        .spawn()
+        .insert_bundle(SpriteBundle {
+            material: material_assets.add(Color::rgb(0.8, 0.2, 0.2).into()),
+            sprite: Sprite::new(Vec2::new(20.0, 10.0)),
+            transform: Transform::from_translation(Vec3::new(-100.0, 5.0, 0.0)),
+            ..Default::default()
+        })
+        .insert(Position(Vec2::new(-100.0, 5.0)))
+        .insert(Hitbox {
+            shape: CollisionShape::Rect(Vec2::new(20.0, 10.0)),
+            col_type: ColliderType::Hazard,
+        });
+
+    commands
+        .spawn()
+        .insert(Position(Vec2::new(0.0, -400.0)))
+        .insert(Hitbox {
+            shape: CollisionShape::Rect(Vec2::new(4000.0, 20.0)),
+            col_type: ColliderType::Hazard,
+        });
+}
+
+pub fn load_platforms(
+    mut need_to_load: ResMut<NeedToLoad>,
+    server: Res<AssetServer>,
+    mut moving_platforms: ResMut<MovingPlatformsHandle>,
+) {
+    moving_platforms.0 = server.load("level.platforms.ron");
+    need_to_load
+        .handles
+        .push(moving_platforms.0.clone_untyped());
+}
+
+// respawns every moving platform whenever their definitions are loaded or changed
+pub fn spawn_moving_platforms(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<MovingPlatforms>>,
+    moving_platforms: Res<Assets<MovingPlatforms>>,
+    mut material_assets: ResMut<Assets<ColorMaterial>>,
+    existing_q: Query<Entity, With<MovingPlatform>>,
+) {
+    for event in events.iter() {
+        let defs = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                match moving_platforms.get(handle) {
+                    Some(defs) => defs,
+                    None => continue,
+                }
+            }
+            AssetEvent::Removed { .. } => continue,
+        };
+
+        for entity in existing_q.iter() {
+            commands.entity(entity).despawn();
+        }
+
+        let material = material_assets.add(Color::rgb(0.3, 0.4, 0.5).into());
+        for def in defs.platforms.iter() {
+            let start = match def.waypoints.first() {
+                Some(&start) => start,
+                None => continue,
+            };
+            commands
+                .spawn()
+                .insert_bundle(SpriteBundle {
+                    material: material.clone(),
+                    sprite: Sprite::new(def.size),
+                    transform: Transform::from_translation(start.extend(0.0)),
+                    ..Default::default()
+                })
+                .insert(Ground)
+                .insert(MovingPlatform {
+                    speed: def.speed,
+                    mode: def.mode,
+                    waypoints: def.waypoints.clone(),
+                    target: 0,
+                    forward: true,
+                })
+                .insert(Position(start))
+                .insert(PreviousPosition(start))
+                .insert(Velocity(Vec2::ZERO))
+                .insert(Displacement::default())
+                .insert(Hitbox {
+                    shape: CollisionShape::Rect(def.size),
+                    col_type: ColliderType::Ground,
+                });
+        }
+    }
+}
+
+// steers platforms towards their next waypoint, landing exactly on it instead of overshooting
+pub fn update_platform_velocities(
+    mut platforms_q: Query<(&mut MovingPlatform, &Position, &mut Velocity)>,
+    timestep: Res<PhysicsTimestep>,
+) {
+    for (mut platform, p, mut v) in platforms_q.iter_mut() {
+        if platform.waypoints.len() < 2 {
+            v.0 = Vec2::ZERO;
+            continue;
+        }
+
+        let to_target = platform.waypoints[platform.target] - p.0;
+        let step = platform.speed * timestep.dt;
+        if to_target.length() <= step {
+            v.0 = to_target / timestep.dt;
+            platform.advance_target();
+        } else {
+            v.0 = to_target.normalize() * platform.speed;
+        }
+    }
 }
@@ -0,0 +1,236 @@
+use crate::loader::NeedToLoad;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use std::collections::{HashMap, HashSet};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Down,
+    Jump,
+    Dash,
+}
+
+// keys and gamepad buttons bound to each action, any of which triggers it
+#[derive(serde::Deserialize, TypeUuid)]
+#[uuid = "5e0b7c2a-91d4-4f38-b6a1-3c8e2d7f4a19"]
+pub struct InputMap {
+    pub keys: HashMap<Action, Vec<KeyCode>>,
+    pub buttons: HashMap<Action, Vec<GamepadButtonType>>,
+    // how far the left stick has to be pushed before it moves the player
+    pub stick_deadzone: f32,
+}
+
+#[derive(Default)]
+pub struct InputMapHandle(pub Handle<InputMap>);
+
+// the gamepad the player is using, if one is plugged in, and every pad that could take over
+// when it's unplugged
+#[derive(Default)]
+pub struct ActiveGamepad {
+    pub active: Option<Gamepad>,
+    connected: Vec<Gamepad>,
+}
+
+impl ActiveGamepad {
+    pub fn connect(self: &mut Self, gamepad: Gamepad) {
+        if !self.connected.contains(&gamepad) {
+            self.connected.push(gamepad);
+        }
+        if self.active.is_none() {
+            self.active = Some(gamepad);
+        }
+    }
+
+    // hands over to the longest connected pad left, if any
+    pub fn disconnect(self: &mut Self, gamepad: Gamepad) {
+        self.connected.retain(|&g| g != gamepad);
+        if self.active == Some(gamepad) {
+            self.active = self.connected.first().copied();
+        }
+    }
+}
+
+// actions held this frame and pressed since the last physics step, so a press is seen by exactly
+// one step however many run each frame. player systems read these instead of raw keys
+#[derive(serde::Serialize, serde::Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct Actions {
+    pub pressed: HashSet<Action>,
+    pub just_pressed: HashSet<Action>,
+    // how hard the player is pushing left (-1) or right (1)
+    pub move_x: f32,
+}
+
+impl Actions {
+    pub fn move_x(self: &Self) -> f32 {
+        self.move_x
+    }
+
+    pub fn pressed(self: &Self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(self: &Self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+pub fn load_input_map(
+    mut need_to_load: ResMut<NeedToLoad>,
+    server: Res<AssetServer>,
+    mut input_map: ResMut<InputMapHandle>,
+) {
+    input_map.0 = server.load("controls.input.ron");
+    need_to_load.handles.push(input_map.0.clone_untyped());
+}
+
+// picks up the first gamepad plugged in, and falls back to another one when it's unplugged
+pub fn connect_gamepads(
+    mut gamepad_events: EventReader<GamepadEvent>,
+    mut active_gamepad: ResMut<ActiveGamepad>,
+) {
+    for GamepadEvent(gamepad, event_type) in gamepad_events.iter() {
+        match event_type {
+            GamepadEventType::Connected => active_gamepad.connect(*gamepad),
+            GamepadEventType::Disconnected => active_gamepad.disconnect(*gamepad),
+            _ => {}
+        }
+    }
+}
+
+// rescales stick values past the deadzone so movement still starts from zero
+pub fn apply_deadzone(value: f32, deadzone: f32) -> f32 {
+    if value.abs() <= deadzone {
+        0.0
+    } else {
+        value.signum() * ((value.abs() - deadzone) / (1.0 - deadzone)).min(1.0)
+    }
+}
+
+pub fn update_actions(
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    active_gamepad: Res<ActiveGamepad>,
+    input_maps: Res<Assets<InputMap>>,
+    input_map_handle: Res<InputMapHandle>,
+    mut actions: ResMut<Actions>,
+) {
+    actions.pressed.clear();
+    actions.move_x = 0.0;
+    let input_map = match input_maps.get(&input_map_handle.0) {
+        Some(input_map) => input_map,
+        None => return,
+    };
+
+    for (&action, keys) in input_map.keys.iter() {
+        if keys.iter().any(|&key| keyboard_input.pressed(key)) {
+            actions.pressed.insert(action);
+        }
+        if keys.iter().any(|&key| keyboard_input.just_pressed(key)) {
+            actions.just_pressed.insert(action);
+        }
+    }
+
+    // left wins if both keys are held
+    let keys_x = if actions.pressed(Action::MoveLeft) {
+        -1.0
+    } else if actions.pressed(Action::MoveRight) {
+        1.0
+    } else {
+        0.0
+    };
+
+    let mut stick_x = 0.0;
+    if let Some(gamepad) = active_gamepad.active {
+        for (&action, buttons) in input_map.buttons.iter() {
+            let mut buttons = buttons
+                .iter()
+                .map(|&button_type| GamepadButton(gamepad, button_type));
+            if buttons
+                .clone()
+                .any(|button| gamepad_buttons.pressed(button))
+            {
+                actions.pressed.insert(action);
+            }
+            if buttons.any(|button| gamepad_buttons.just_pressed(button)) {
+                actions.just_pressed.insert(action);
+            }
+        }
+
+        let x = gamepad_axes
+            .get(GamepadAxis(gamepad, GamepadAxisType::LeftStickX))
+            .unwrap_or(0.0);
+        stick_x = apply_deadzone(x, input_map.stick_deadzone);
+        // the stick counts as the move buttons too, for anything that only cares about direction
+        if stick_x < 0.0 {
+            actions.pressed.insert(Action::MoveLeft);
+        } else if stick_x > 0.0 {
+            actions.pressed.insert(Action::MoveRight);
+        }
+    }
+
+    // full speed from the keys, otherwise as far as the stick is pushed
+    actions.move_x = if keys_x != 0.0 { keys_x } else { stick_x };
+}
+
+// presses have been seen by the physics step that just ran
+pub fn consume_presses(mut actions: ResMut<Actions>) {
+    actions.just_pressed.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadzone_ignores_small_pushes_up_to_its_edge() {
+        assert_eq!(apply_deadzone(0.0, 0.2), 0.0);
+        assert_eq!(apply_deadzone(0.1, 0.2), 0.0);
+        assert_eq!(apply_deadzone(0.2, 0.2), 0.0);
+        assert_eq!(apply_deadzone(-0.2, 0.2), 0.0);
+    }
+
+    #[test]
+    fn deadzone_keeps_the_sign() {
+        assert!(apply_deadzone(0.5, 0.2) > 0.0);
+        assert!(apply_deadzone(-0.5, 0.2) < 0.0);
+        assert_eq!(apply_deadzone(-0.5, 0.2), -apply_deadzone(0.5, 0.2));
+    }
+
+    #[test]
+    fn deadzone_rescales_the_rest_of_the_stick_up_to_one() {
+        assert!((apply_deadzone(0.6, 0.2) - 0.5).abs() < 1e-6);
+        assert_eq!(apply_deadzone(1.0, 0.2), 1.0);
+        assert_eq!(apply_deadzone(-1.0, 0.2), -1.0);
+        // sticks can report slightly past 1 on the diagonals
+        assert_eq!(apply_deadzone(1.05, 0.2), 1.0);
+    }
+
+    #[test]
+    fn unplugging_the_active_gamepad_falls_back_to_another() {
+        let mut gamepads = ActiveGamepad::default();
+        gamepads.connect(Gamepad(0));
+        gamepads.connect(Gamepad(1));
+        assert_eq!(gamepads.active, Some(Gamepad(0)));
+
+        gamepads.disconnect(Gamepad(0));
+        assert_eq!(gamepads.active, Some(Gamepad(1)));
+
+        gamepads.disconnect(Gamepad(1));
+        assert_eq!(gamepads.active, None);
+
+        gamepads.connect(Gamepad(2));
+        assert_eq!(gamepads.active, Some(Gamepad(2)));
+    }
+
+    #[test]
+    fn unplugging_another_gamepad_keeps_the_active_one() {
+        let mut gamepads = ActiveGamepad::default();
+        gamepads.connect(Gamepad(0));
+        gamepads.connect(Gamepad(1));
+        gamepads.disconnect(Gamepad(1));
+        assert_eq!(gamepads.active, Some(Gamepad(0)));
+    }
+}
@@ -1,29 +1,63 @@
+mod checkpoint;
 mod ground;
+mod input;
 mod loader;
 mod physics;
 mod player;
 mod player_fsm;
-use crate::ground::spawn_ground;
+mod replay;
+#[cfg(test)]
+mod testing;
+use crate::checkpoint::{activate_checkpoints, spawn_checkpoints};
+use crate::ground::{
+    load_platforms, spawn_ground, spawn_hazards, spawn_moving_platforms,
+    update_platform_velocities, MovingPlatforms, MovingPlatformsHandle,
+};
+use crate::input::{
+    connect_gamepads, consume_presses, load_input_map, update_actions, Actions, ActiveGamepad,
+    InputMap, InputMapHandle,
+};
 use crate::loader::load_state_run_criteria;
 use crate::loader::LoaderState;
 use crate::physics::{
-    check_collisions, clean_up_collisions, load_physics, update_positions, update_translation,
-    update_velocities, PhysicsSettings, PhysicsSettingsHandle, TIME_STEP,
+    check_collisions, clean_up_collisions, integrate, load_physics, store_previous_positions,
+    tick_drop_through, update_positions, update_spatial_hash, update_swept_positions,
+    update_translation, CollisionMatrix, CollisionMatrixHandle, PhysicsSettings,
+    PhysicsSettingsHandle, PhysicsStage, PhysicsTimestep, SpatialHash,
 };
 use crate::player::{
-    handle_player_collides_ground, player_horizontal_accel, player_input, spawn_player,
+    carry_player_on_platforms, count_down_buffers, fall_off_ledges, forget_hazards,
+    handle_player_collides_ground, player_drop_through, player_horizontal_accel, player_input,
+    player_jump, respawn_player, snap_player_to_ground, spawn_player, update_dash_timers,
+    update_jump_timers, update_player_fsm, update_player_hurtbox, update_player_ray_position,
+    SpawnPoint,
 };
-use bevy::{core::FixedTimestep, prelude::*};
+use crate::replay::{record_or_replay_actions, restart_player, toggle_replay, Replay};
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
 use bevy_asset_ron::*;
 
 #[derive(Clone, Hash, Debug, Eq, PartialEq, SystemLabel)]
 enum System {
     LoaderSet,
+    Actions,
+    Replay,
+    PlayerInput,
+    StorePrevious,
+    Integrate,
     UpdatePosition,
-    UpdateTranslation,
+    Broadphase,
+    SweptPosition,
     Collision,
+    PlayerCollision,
+    GroundSnap,
+    PlayerFSM,
     CollisionCleanUp,
-    PhysicsSet,
+}
+
+#[derive(Clone, Hash, Debug, Eq, PartialEq, StageLabel)]
+enum GameStage {
+    Physics,
 }
 
 fn main() {
@@ -34,10 +68,24 @@ fn main() {
         })
         .add_plugins(DefaultPlugins)
         .add_plugin(RonAssetPlugin::<PhysicsSettings>::new(&["physics.ron"]))
+        .add_plugin(RonAssetPlugin::<CollisionMatrix>::new(&["collision.ron"]))
+        .add_plugin(RonAssetPlugin::<MovingPlatforms>::new(&["platforms.ron"]))
+        .add_plugin(RonAssetPlugin::<InputMap>::new(&["input.ron"]))
         .init_resource::<PhysicsSettingsHandle>()
+        .init_resource::<CollisionMatrixHandle>()
+        .init_resource::<MovingPlatformsHandle>()
+        .init_resource::<InputMapHandle>()
+        .init_resource::<Actions>()
+        .init_resource::<ActiveGamepad>()
+        .init_resource::<Replay>()
+        .init_resource::<SpatialHash>()
+        .init_resource::<PhysicsTimestep>()
+        .init_resource::<SpawnPoint>()
         .add_startup_system(setup)
         .add_startup_system(spawn_player)
         .add_startup_system(spawn_ground)
+        .add_startup_system(spawn_hazards)
+        .add_startup_system(spawn_checkpoints)
         .add_startup_system(loader::init)
         .add_system_set(
             SystemSet::new()
@@ -50,7 +98,9 @@ fn main() {
         .add_system_set(
             SystemSet::new()
                 .with_run_criteria(loader::on_enter_loading)
-                .with_system(load_physics),
+                .with_system(load_physics)
+                .with_system(load_platforms)
+                .with_system(load_input_map),
         )
         .add_system_set(
             SystemSet::new()
@@ -60,44 +110,16 @@ fn main() {
                 )
                 .with_system(loader::check_loaded),
         )
-        .add_system_set(
-            SystemSet::new()
-                .with_run_criteria(
-                    load_state_run_criteria
-                        .config(|c| c.0 = Some(LoaderState::Loaded))
-                        .label("player input run criteria"),
-                )
-                .after(System::LoaderSet)
-                .before(System::PhysicsSet)
-                .with_system(player_input)
-                .with_system(player_horizontal_accel),
-        )
-        .add_system_set(
-            SystemSet::new()
-                .label(System::PhysicsSet)
-                .with_run_criteria(
-                    load_state_run_criteria
-                        .config(|c| c.0 = Some(LoaderState::Loaded))
-                        .label("physics set run criteria"),
-                )
-                .after(System::LoaderSet)
-                .before(System::UpdateTranslation)
-                .with_run_criteria(FixedTimestep::step(TIME_STEP as f64))
-                .with_system(update_velocities.before(System::UpdatePosition))
-                .with_system(update_positions.label(System::UpdatePosition))
-                .with_system(
-                    check_collisions
-                        .label(System::Collision)
-                        .after(System::UpdatePosition),
-                )
-                .with_system(
-                    handle_player_collides_ground
-                        .after(System::Collision)
-                        .before(System::CollisionCleanUp),
-                )
-                .with_system(clean_up_collisions.label(System::CollisionCleanUp)),
+        .add_stage_after(CoreStage::Update, GameStage::Physics, physics_stage())
+        .add_system(connect_gamepads.before(System::Actions))
+        .add_system(update_actions.label(System::Actions))
+        .add_system(toggle_replay)
+        .add_system(update_player_hurtbox)
+        .add_system(spawn_moving_platforms)
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            update_translation.before(TransformSystem::TransformPropagate),
         )
-        .add_system(update_translation.label(System::UpdateTranslation))
         .run();
 }
 
@@ -107,3 +129,109 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         .spawn()
         .insert_bundle(OrthographicCameraBundle::new_2d());
 }
+
+// player input is read once per tick so recorded ticks replay exactly
+fn physics_stage() -> PhysicsStage {
+    let mut tick = SystemStage::parallel();
+    tick.add_system(record_or_replay_actions.label(System::Replay))
+        .add_system(
+            restart_player
+                .label(System::Replay)
+                .before(System::StorePrevious),
+        )
+        .add_system(forget_hazards.after(System::Replay))
+        .add_system(store_previous_positions.label(System::StorePrevious))
+        .add_system(
+            player_input
+                .label(System::PlayerInput)
+                .after(System::Replay),
+        )
+        .add_system(
+            player_drop_through
+                .label(System::PlayerInput)
+                .after(System::Replay),
+        )
+        .add_system(
+            player_horizontal_accel
+                .label(System::PlayerInput)
+                .after(System::Replay),
+        )
+        .add_system(player_jump.after(System::PlayerInput))
+        .add_system(tick_drop_through.after(System::PlayerInput))
+        .add_system(update_platform_velocities.after(System::Replay));
+
+    let mut sub_step = SystemStage::parallel();
+    sub_step
+        .add_system(clean_up_collisions.label(System::CollisionCleanUp))
+        .add_system(
+            integrate
+                .label(System::Integrate)
+                .after(System::CollisionCleanUp),
+        )
+        .add_system(
+            update_positions
+                .label(System::UpdatePosition)
+                .after(System::Integrate),
+        )
+        .add_system(
+            update_spatial_hash
+                .label(System::Broadphase)
+                .after(System::UpdatePosition),
+        )
+        // platforms have moved by now, so the player is carried before its own sweep
+        .add_system(
+            carry_player_on_platforms
+                .after(System::UpdatePosition)
+                .before(System::SweptPosition),
+        )
+        .add_system(
+            update_swept_positions
+                .label(System::SweptPosition)
+                .after(System::Broadphase),
+        )
+        .add_system(
+            update_player_ray_position
+                .after(System::SweptPosition)
+                .before(System::Collision),
+        )
+        .add_system(
+            check_collisions
+                .label(System::Collision)
+                .after(System::SweptPosition),
+        )
+        .add_system(
+            handle_player_collides_ground
+                .label(System::PlayerCollision)
+                .after(System::Collision),
+        )
+        .add_system(fall_off_ledges.after(System::PlayerCollision))
+        .add_system(activate_checkpoints.after(System::Collision));
+
+    let mut after_sub_steps = SystemStage::parallel();
+    after_sub_steps
+        .add_system(snap_player_to_ground.label(System::GroundSnap))
+        .add_system(
+            update_jump_timers
+                .after(System::GroundSnap)
+                .before(System::PlayerFSM),
+        )
+        .add_system(
+            update_dash_timers
+                .after(System::GroundSnap)
+                .before(System::PlayerFSM),
+        )
+        .add_system(
+            update_player_fsm
+                .label(System::PlayerFSM)
+                .after(System::GroundSnap),
+        )
+        .add_system(respawn_player.after(System::PlayerFSM))
+        .add_system(count_down_buffers.after(System::PlayerFSM))
+        .add_system(consume_presses.after(System::PlayerFSM));
+
+    PhysicsStage {
+        tick,
+        sub_step,
+        after_sub_steps,
+    }
+}
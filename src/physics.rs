@@ -1,80 +1,538 @@
-use crate::loader::NeedToLoad;
-use bevy::{prelude::*, reflect::TypeUuid};
+use crate::loader::{LoaderState, NeedToLoad};
+use bevy::{prelude::*, reflect::TypeUuid, utils::HashMap};
 
-pub const TIME_STEP: f32 = 1.0 / 60.0;
+// the tick rate and sub-steps from the physics settings, picked up between frames so they can be
+// changed while the game is running
+pub struct PhysicsTimestep {
+    // seconds simulated by each physics tick
+    pub dt: f32,
+    pub sub_steps: u32,
+    accumulator: f64,
+    // the last tick rate in the settings that couldn't be used, so it's only warned about once
+    rejected_tick_rate: Option<f32>,
+}
+
+impl Default for PhysicsTimestep {
+    fn default() -> Self {
+        PhysicsTimestep {
+            dt: 1.0 / 60.0,
+            sub_steps: 1,
+            accumulator: 0.0,
+            rejected_tick_rate: None,
+        }
+    }
+}
+
+impl PhysicsTimestep {
+    // how far into the next tick the current frame is, from 0 to 1
+    pub fn overstep_percentage(self: &Self) -> f32 {
+        (self.accumulator / self.dt as f64) as f32
+    }
+
+    // seconds each body is moved and collided for at a time
+    pub fn sub_dt(self: &Self) -> f32 {
+        self.dt / self.sub_steps.max(1) as f32
+    }
+}
+
+// the most ticks run in a single frame
+const MAX_TICKS_PER_FRAME: u32 = 5;
+
+// runs the physics once per tick. gameplay (input, timers, the player FSM) runs once a tick in
+// `tick` and `after_sub_steps`, while moving and colliding is split into `sub_steps` runs of
+// `sub_step` so fast bodies don't skip past things, without changing how long a tick lasts
+pub struct PhysicsStage {
+    pub tick: SystemStage,
+    pub sub_step: SystemStage,
+    pub after_sub_steps: SystemStage,
+}
+
+impl PhysicsStage {
+    // runs one whole tick, however much time has built up
+    pub fn step(self: &mut Self, world: &mut World) {
+        let sub_steps = world
+            .get_resource::<PhysicsTimestep>()
+            .map_or(1, |timestep| timestep.sub_steps.max(1));
+        self.tick.run(world);
+        for _ in 0..sub_steps {
+            self.sub_step.run(world);
+        }
+        self.after_sub_steps.run(world);
+    }
+}
+
+impl Stage for PhysicsStage {
+    fn run(&mut self, world: &mut World) {
+        // no time builds up before there's anything to simulate
+        if world.get_resource::<LoaderState>() != Some(&LoaderState::Loaded) {
+            return;
+        }
+
+        let settings = world
+            .get_resource::<PhysicsSettingsHandle>()
+            .and_then(|handle| {
+                world
+                    .get_resource::<Assets<PhysicsSettings>>()?
+                    .get(&handle.0)
+                    .map(|s| (s.tick_rate, s.sub_steps))
+            });
+        let delta = world
+            .get_resource::<Time>()
+            .map_or(0.0, |time| time.delta_seconds_f64());
+        let mut timestep = world.get_resource_mut::<PhysicsTimestep>().unwrap();
+        if let Some((tick_rate, sub_steps)) = settings {
+            // a bad tick rate keeps the last good one, or the simulation would stop or never
+            // catch up
+            if tick_rate > 0.0 && tick_rate.is_finite() {
+                timestep.dt = 1.0 / tick_rate;
+                timestep.rejected_tick_rate = None;
+            } else if timestep.rejected_tick_rate != Some(tick_rate) {
+                warn!(
+                    "ignoring tick_rate {} from the physics settings, it has to be above 0",
+                    tick_rate
+                );
+                timestep.rejected_tick_rate = Some(tick_rate);
+            }
+            timestep.sub_steps = sub_steps.max(1);
+        }
+        timestep.accumulator += delta;
+
+        for _ in 0..MAX_TICKS_PER_FRAME {
+            let mut timestep = world.get_resource_mut::<PhysicsTimestep>().unwrap();
+            if timestep.accumulator < timestep.dt as f64 {
+                return;
+            }
+            timestep.accumulator -= timestep.dt as f64;
+            self.step(world);
+        }
+
+        // after a long hitch the game slows down instead of running more and more ticks to
+        // catch up
+        let mut timestep = world.get_resource_mut::<PhysicsTimestep>().unwrap();
+        timestep.accumulator %= timestep.dt as f64;
+    }
+}
 
 #[derive(Component)]
 pub struct Position(pub Vec2);
+
+// where the entity was at the start of the last physics step, so rendering can blend towards the
+// current position between steps
+#[derive(Component)]
+pub struct PreviousPosition(pub Vec2);
 #[derive(Component)]
 pub struct Velocity(pub Vec2);
 
 #[derive(Component)]
 pub struct Acceleration(pub Vec2);
 
-#[derive(serde::Deserialize, TypeUuid, Component)]
+// how far the body moves during the current step, as worked out by the integrator
+#[derive(Component, Default)]
+pub struct Displacement(pub Vec2);
+
+#[derive(serde::Deserialize, Clone, Copy)]
+pub enum Integrator {
+    // updates velocity first, then moves by the new velocity
+    SymplecticEuler,
+    // moves by the old velocity plus half the acceleration, exact while acceleration is constant
+    VelocityVerlet,
+}
+
+impl Integrator {
+    // returns how far the body moves over the step and its velocity at the end of it
+    pub fn step(self: &Self, v: Vec2, a: Vec2, dt: f32) -> (Vec2, Vec2) {
+        match self {
+            Integrator::SymplecticEuler => {
+                let v = v + a * dt;
+                (v * dt, v)
+            }
+            Integrator::VelocityVerlet => (v * dt + a * (0.5 * dt * dt), v + a * dt),
+        }
+    }
+}
+
+// hurtboxes ignore one-way hitboxes while this counts down to zero
+#[derive(Component, Default)]
+pub struct DropThrough(pub u32);
+
+#[derive(serde::Deserialize, TypeUuid, Component, Clone)]
 #[uuid = "fae44c41-c109-446a-a48f-0d7742ab877a"]
 pub struct PhysicsSettings {
     pub normal_gravity: f32,
     pub hold_gravity: f32,
     pub initial_jump_velocity: f32,
+    // physics ticks per second, each moving and colliding bodies in sub_steps smaller pieces so
+    // fast bodies don't skip past things. gameplay timers still count whole ticks
+    pub tick_rate: f32,
+    pub sub_steps: u32,
+    pub integrator: Integrator,
     pub horizontal_a: f32,
+    // horizontal acceleration while the player isn't standing on anything
+    pub air_horizontal_a: f32,
+    pub max_run_speed: f32,
+    pub max_fall_speed: f32,
     pub friction: f32,
     pub stopping_horizontal_speed: f32,
+    pub player_hurtbox: CollisionShape,
+    // how far below its feet the player is pulled down onto the ground, so it sticks to slopes
+    pub ground_snap_distance: f32,
+    // how many steps the player falls through one-way platforms after dropping down
+    pub drop_through_steps: u32,
+    // fixed ticks a jump is still allowed after running off a ledge, 0 for none
+    pub coyote_ticks: u32,
+    // fixed ticks a jump pressed before landing is remembered for, 0 for none
+    pub jump_buffer_ticks: u32,
+    // jumps allowed in the air before touching the ground again, and their upwards velocity
+    pub air_jumps: u32,
+    pub air_jump_velocity: f32,
+    // fastest the player slides down a wall it's holding towards
+    pub wall_slide_speed: f32,
+    // velocity kicking the player off a wall, with x pointing away from it
+    pub wall_jump_velocity: Vec2,
+    // fixed ticks after a wall jump that sideways input is ignored for
+    pub wall_jump_lock_ticks: u32,
+    // horizontal speed of a dash, which ignores gravity and input while it lasts
+    pub dash_speed: f32,
+    // fixed ticks a dash lasts, during which hazards can't hurt the player
+    pub dash_ticks: u32,
+    // fixed ticks after a dash starts before the next one is allowed
+    pub dash_cooldown_ticks: u32,
+    // fixed ticks a dash pressed too early waits to happen for, 0 for none
+    pub dash_buffer_ticks: u32,
+    // fixed ticks between the player dying and coming back at the spawn point
+    pub respawn_delay_ticks: u32,
 }
 
 #[derive(Default)]
 pub struct PhysicsSettingsHandle(pub Handle<PhysicsSettings>);
 
+// pairs of hurtbox and hitbox types that are allowed to collide
+#[derive(serde::Deserialize, TypeUuid)]
+#[uuid = "3b8a3a5e-5c7d-4f0e-9a57-2d1f7c0b6e41"]
+pub struct CollisionMatrix {
+    pub interactions: Vec<(ColliderType, ColliderType)>,
+}
+
+impl CollisionMatrix {
+    pub fn interacts(&self, hurt_type: ColliderType, hit_type: ColliderType) -> bool {
+        self.interactions.contains(&(hurt_type, hit_type))
+    }
+}
+
+#[derive(Default)]
+pub struct CollisionMatrixHandle(pub Handle<CollisionMatrix>);
+
 pub fn load_physics(
     mut need_to_load: ResMut<NeedToLoad>,
     server: Res<AssetServer>,
     mut physics_settings: ResMut<PhysicsSettingsHandle>,
+    mut collision_matrix: ResMut<CollisionMatrixHandle>,
 ) {
     physics_settings.0 = server.load("settings.physics.ron");
     need_to_load
         .handles
         .push(physics_settings.0.clone_untyped());
+
+    collision_matrix.0 = server.load("settings.collision.ron");
+    need_to_load
+        .handles
+        .push(collision_matrix.0.clone_untyped());
+}
+
+// works out each body's velocity and displacement for the step in one place, so the result doesn't
+// depend on the order the movement systems run in
+pub fn integrate(
+    mut query: Query<(&mut Velocity, Option<&Acceleration>, &mut Displacement)>,
+    timestep: Res<PhysicsTimestep>,
+    physics_settings: Res<Assets<PhysicsSettings>>,
+    physics_settings_handle: Res<PhysicsSettingsHandle>,
+) {
+    let s = physics_settings.get(&physics_settings_handle.0);
+    let integrator = s.map_or(Integrator::SymplecticEuler, |s| s.integrator);
+
+    for (mut v, a, mut displacement) in query.iter_mut() {
+        let (d, new_v) = match (a, s) {
+            // only bodies driven by acceleration are held to the speed caps
+            (Some(a), Some(s)) => capped_step(
+                integrator,
+                v.0,
+                a.0,
+                timestep.sub_dt(),
+                s.max_run_speed,
+                s.max_fall_speed,
+            ),
+            (a, _) => integrator.step(v.0, a.map_or(Vec2::ZERO, |a| a.0), timestep.sub_dt()),
+        };
+        displacement.0 = d;
+        v.0 = new_v;
+    }
+}
+
+// holds horizontal speed to max_run_speed either way and stops falling faster than max_fall_speed
+pub fn cap_speed(v: Vec2, max_run_speed: f32, max_fall_speed: f32) -> Vec2 {
+    Vec2::new(
+        v.x.max(-max_run_speed).min(max_run_speed),
+        v.y.max(-max_fall_speed),
+    )
+}
+
+pub fn capped_step(
+    integrator: Integrator,
+    v: Vec2,
+    a: Vec2,
+    dt: f32,
+    max_run_speed: f32,
+    max_fall_speed: f32,
+) -> (Vec2, Vec2) {
+    let (d, new_v) = integrator.step(v, a, dt);
+    // acceleration can't run past the cap, but faster speeds set directly (like a dash) are kept
+    let capped = cap_speed(new_v, max_run_speed.max(v.x.abs()), max_fall_speed);
+    if capped == new_v {
+        return (d, new_v);
+    }
+    // redo the step with the acceleration that ends up right at the cap
+    integrator.step(v, (capped - v) / dt, dt)
+}
+
+pub fn update_positions(mut q: Query<(&mut Position, &Displacement), Without<Hurtbox>>) {
+    for (mut p, displacement) in q.iter_mut() {
+        p.0 += displacement.0;
+    }
+}
+
+// moves hurtboxes along their velocity, stopping them at the hitboxes they would pass into
+pub fn update_swept_positions(
+    mut hurtboxes: Query<(
+        &Hurtbox,
+        &mut Position,
+        &Displacement,
+        &mut Collisions,
+        Option<&DropThrough>,
+    )>,
+    hitboxes: Query<(&Hitbox, &Position), Without<Hurtbox>>,
+    spatial_hash: Res<SpatialHash>,
+    collision_matrices: Res<Assets<CollisionMatrix>>,
+    collision_matrix_handle: Res<CollisionMatrixHandle>,
+) {
+    // the physics stage can tick before the collision matrix has finished loading
+    let matrix = match collision_matrices.get(&collision_matrix_handle.0) {
+        Some(matrix) => matrix,
+        None => return,
+    };
+
+    for (hurtbox, mut p, displacement, mut collisions, drop_through) in hurtboxes.iter_mut() {
+        let dropping = drop_through.map_or(false, |d| d.0 > 0);
+        let displacement = displacement.0;
+        let (start_min, start_max) = hurtbox.shape.bounds(p.0);
+        let (end_min, end_max) = hurtbox.shape.bounds(p.0 + displacement);
+        // later sweeps only cover part of the first one's path, so the candidates are shared
+        let candidates = spatial_hash.query(start_min.min(end_min), start_max.max(end_max));
+
+        let (end, hits) = sweep_move(p.0, displacement, |from, remaining| {
+            let mut earliest: Option<(f32, CollisionData)> = None;
+            for &hit_entity in candidates.iter() {
+                let (hitbox, hitbox_position) = match hitboxes.get(hit_entity) {
+                    Ok(hitbox) => hitbox,
+                    Err(_) => continue,
+                };
+                if dropping && hitbox.col_type.is_one_way() {
+                    continue;
+                }
+                if let Some((t, collision)) = hurtbox.sweep_collision(
+                    &Position(from),
+                    remaining,
+                    hitbox,
+                    hitbox_position,
+                    hit_entity,
+                    matrix,
+                ) {
+                    if earliest
+                        .as_ref()
+                        .map_or(true, |(earliest_t, _)| t < *earliest_t)
+                    {
+                        earliest = Some((t, collision));
+                    }
+                }
+            }
+            earliest
+        });
+        p.0 = end;
+        collisions.0.extend(hits);
+    }
+}
+
+// a body blocked on both axes is stopped after two sweeps, the rest are for surfaces that only
+// turn the move aside
+const MAX_SWEEPS: usize = 4;
+
+// moves from `position` along `displacement`, stopping at the earliest hit `sweep` finds and
+// sweeping what's left of the move along the surface again, so a body pressed against a wall
+// can't fall through a floor. any move left over after the last sweep is dropped
+pub fn sweep_move(
+    position: Vec2,
+    displacement: Vec2,
+    mut sweep: impl FnMut(Vec2, Vec2) -> Option<(f32, CollisionData)>,
+) -> (Vec2, Vec<CollisionData>) {
+    let mut position = position;
+    let mut remaining = displacement;
+    let mut hits = Vec::new();
+    for _ in 0..MAX_SWEEPS {
+        if remaining == Vec2::ZERO {
+            break;
+        }
+        match sweep(position, remaining) {
+            Some((t, collision)) => {
+                position += remaining * t;
+                // only the part of the move into the surface is stopped
+                remaining *= 1.0 - t;
+                remaining -= collision.normal * remaining.dot(collision.normal).min(0.0);
+                hits.push(collision);
+            }
+            None => {
+                position += remaining;
+                remaining = Vec2::ZERO;
+            }
+        }
+    }
+    (position, hits)
 }
 
-pub fn update_velocities(mut query: Query<(&mut Velocity, &Acceleration)>) {
-    for (mut v, a) in query.iter_mut() {
-        v.0 += a.0 * TIME_STEP;
+pub fn tick_drop_through(mut q: Query<&mut DropThrough>) {
+    for mut drop_through in q.iter_mut() {
+        drop_through.0 = drop_through.0.saturating_sub(1);
     }
 }
 
-pub fn update_positions(mut q: Query<(&mut Position, &Velocity)>) {
-    for (mut p, v) in q.iter_mut() {
-        p.0 += v.0 * TIME_STEP;
+pub fn store_previous_positions(mut q: Query<(&Position, &mut PreviousPosition)>) {
+    for (p, mut previous) in q.iter_mut() {
+        previous.0 = p.0;
     }
 }
 
-pub fn update_translation(mut q: Query<(&Position, &mut Transform)>) {
-    for (p, mut t) in q.iter_mut() {
-        t.translation = p.0.extend(0.0);
+// moves an entity without blending from where it was, so respawns snap instead of smear
+pub fn teleport(p: &mut Position, previous: &mut PreviousPosition, to: Vec2) {
+    p.0 = to;
+    previous.0 = to;
+}
+
+pub fn update_translation(
+    mut q: Query<(&Position, Option<&PreviousPosition>, &mut Transform)>,
+    timestep: Res<PhysicsTimestep>,
+) {
+    // how far into the next physics step the frame is being drawn
+    let alpha = timestep.overstep_percentage().min(1.0);
+
+    for (p, previous, mut t) in q.iter_mut() {
+        let position = match previous {
+            Some(previous) => previous.0.lerp(p.0, alpha),
+            None => p.0,
+        };
+        t.translation = position.extend(0.0);
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(serde::Deserialize, Clone, Copy)]
 pub enum CollisionShape {
     Rect(Vec2),
     Ray(Vec2),
+    Circle(f32),
+    // vertical line segment of length 2 * half_height, grown by radius
+    Capsule { half_height: f32, radius: f32 },
+    // right triangle filling the box, with the surface running between opposite corners
+    Slope { size: Vec2, rises_right: bool },
+}
+
+impl CollisionShape {
+    // returns the min and max corners of the box containing the shape
+    pub fn bounds(self: &Self, position: Vec2) -> (Vec2, Vec2) {
+        match self {
+            &CollisionShape::Rect(size) | &CollisionShape::Slope { size, .. } => {
+                (position - size / 2.0, position + size / 2.0)
+            }
+            &CollisionShape::Ray(ray) => {
+                (position.min(position + ray), position.max(position + ray))
+            }
+            &CollisionShape::Circle(radius) => (position - radius, position + radius),
+            &CollisionShape::Capsule {
+                half_height,
+                radius,
+            } => {
+                let extents = Vec2::new(radius, half_height + radius);
+                (position - extents, position + extents)
+            }
+        }
+    }
+
+    // circles are capsules without any height
+    fn capsule(self: &Self) -> Option<(f32, f32)> {
+        match self {
+            &CollisionShape::Circle(radius) => Some((0.0, radius)),
+            &CollisionShape::Capsule {
+                half_height,
+                radius,
+            } => Some((half_height, radius)),
+            _ => None,
+        }
+    }
 }
 
 pub struct CollisionData {
     pub entity: Entity,
     pub direction: Collision,
+    // how far the hurtbox has to move along the normal to stop overlapping
+    pub depth: f32,
+    // points out of the hitbox towards the hurtbox
+    pub normal: Vec2,
+    pub point: Vec2,
     pub collision_type: CollisionType,
 }
 
-pub enum CollisionType {
-    PlayerHitsGround { ground_pos: Vec2, ground_size: Vec2 },
-    PlayerRayHitsGround { ground_pos: Vec2, ground_size: Vec2 },
+// narrow phase result before it is tagged with the entities involved
+pub struct Contact {
+    pub direction: Collision,
+    pub depth: f32,
+    pub normal: Vec2,
+    pub point: Vec2,
+}
+
+impl Contact {
+    // swaps which shape is the hurtbox and which is the hitbox
+    pub fn flip(self) -> Contact {
+        Contact {
+            direction: self.direction.opposite(),
+            depth: self.depth,
+            normal: -self.normal,
+            point: self.point,
+        }
+    }
+}
+
+pub struct CollisionType {
+    pub hit: ColliderType,
+    pub hit_shape: CollisionShape,
 }
 
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ColliderType {
     Player,
     PlayerRay,
     Ground,
+    // ground that can be jumped up through and only stands up to things landing on its top
+    OneWayGround,
+    // spikes, pits and anything else that kills the player on touch
+    Hazard,
+    Checkpoint,
+}
+
+impl ColliderType {
+    pub fn is_one_way(self: &Self) -> bool {
+        matches!(self, ColliderType::OneWayGround)
+    }
+
+    // whether sweeps stop at this kind of hitbox rather than only overlapping it
+    pub fn is_solid(self: &Self) -> bool {
+        !matches!(self, ColliderType::Hazard | ColliderType::Checkpoint)
+    }
 }
 
 #[derive(Component)]
@@ -97,6 +555,42 @@ pub enum Collision {
     Bottom,
 }
 
+impl Collision {
+    // normal of the side of the hitbox that was hit
+    pub fn normal(&self) -> Vec2 {
+        match self {
+            Collision::Left => Vec2::new(-1.0, 0.0),
+            Collision::Right => Vec2::new(1.0, 0.0),
+            Collision::Top => Vec2::new(0.0, 1.0),
+            Collision::Bottom => Vec2::new(0.0, -1.0),
+        }
+    }
+
+    // side facing the normal, picking the closest axis for diagonal normals
+    pub fn from_normal(normal: Vec2) -> Collision {
+        if normal.x.abs() > normal.y.abs() {
+            if normal.x > 0.0 {
+                Collision::Right
+            } else {
+                Collision::Left
+            }
+        } else if normal.y > 0.0 {
+            Collision::Top
+        } else {
+            Collision::Bottom
+        }
+    }
+
+    pub fn opposite(&self) -> Collision {
+        match self {
+            Collision::Left => Collision::Right,
+            Collision::Right => Collision::Left,
+            Collision::Top => Collision::Bottom,
+            Collision::Bottom => Collision::Top,
+        }
+    }
+}
+
 // taken from bevy source code
 pub fn collide_aabb(a_pos: Vec3, a_size: Vec2, b_pos: Vec3, b_size: Vec2) -> Option<Collision> {
     let a_min = a_pos.truncate() - a_size / 2.0;
@@ -145,6 +639,168 @@ pub fn collide_aabb(a_pos: Vec3, a_size: Vec2, b_pos: Vec3, b_size: Vec2) -> Opt
     }
 }
 
+pub fn contact_aabb(a_pos: Vec2, a_size: Vec2, b_pos: Vec2, b_size: Vec2) -> Option<Contact> {
+    let direction = collide_aabb(a_pos.extend(0.0), a_size, b_pos.extend(0.0), b_size)?;
+
+    let a_min = a_pos - a_size / 2.0;
+    let a_max = a_pos + a_size / 2.0;
+    let b_min = b_pos - b_size / 2.0;
+    let b_max = b_pos + b_size / 2.0;
+    let overlap_center = (a_min.max(b_min) + a_max.min(b_max)) / 2.0;
+
+    let (depth, point) = match direction {
+        Collision::Left => (a_max.x - b_min.x, Vec2::new(b_min.x, overlap_center.y)),
+        Collision::Right => (b_max.x - a_min.x, Vec2::new(b_max.x, overlap_center.y)),
+        Collision::Top => (b_max.y - a_min.y, Vec2::new(overlap_center.x, b_max.y)),
+        Collision::Bottom => (a_max.y - b_min.y, Vec2::new(overlap_center.x, b_min.y)),
+    };
+
+    Some(Contact {
+        normal: direction.normal(),
+        direction,
+        depth,
+        point,
+    })
+}
+
+// returns the side of b that a moving box a touches first and the fraction of the displacement
+// travelled when it does. boxes that already overlap are left to the overlap checks.
+pub fn sweep_aabb(
+    a_pos: Vec2,
+    a_size: Vec2,
+    displacement: Vec2,
+    b_pos: Vec2,
+    b_size: Vec2,
+) -> Option<(Collision, f32)> {
+    // grow b by the size of a so a can be treated as a point moving along the displacement
+    let half_size = (a_size + b_size) / 2.0;
+    let b_min = b_pos - half_size;
+    let b_max = b_pos + half_size;
+
+    let (x_entry, x_exit) = sweep_axis(a_pos.x, displacement.x, b_min.x, b_max.x)?;
+    let (y_entry, y_exit) = sweep_axis(a_pos.y, displacement.y, b_min.y, b_max.y)?;
+
+    let entry = x_entry.max(y_entry);
+    let exit = x_exit.min(y_exit);
+
+    if entry >= exit // misses box
+        || entry < 0.0 // already overlapping or moving away
+        || entry > 1.0
+    // stops before box
+    {
+        None
+    } else if x_entry > y_entry {
+        if displacement.x > 0.0 {
+            Some((Collision::Left, entry))
+        } else {
+            Some((Collision::Right, entry))
+        }
+    } else if displacement.y > 0.0 {
+        Some((Collision::Bottom, entry))
+    } else {
+        Some((Collision::Top, entry))
+    }
+}
+
+// how close a sweep has to get before the shapes count as touching
+const SWEEP_SKIN: f32 = 1e-3;
+// a sweep that hasn't touched after this many steps is only grazing past
+const MAX_SWEEP_STEPS: usize = 16;
+
+// gap between a capsule and a box, zero or less once they touch
+fn capsule_box_gap(
+    capsule_pos: Vec2,
+    half_height: f32,
+    radius: f32,
+    box_pos: Vec2,
+    box_size: Vec2,
+) -> f32 {
+    let outside = (capsule_pos - box_pos).abs() - box_size / 2.0 - Vec2::new(0.0, half_height);
+    outside.max(Vec2::ZERO).length() - radius
+}
+
+// sweeps a moving capsule into a box, returning the fraction of the displacement travelled and
+// the contact when they touch. the capsule's bounding box gives the earliest it could touch, then
+// it's moved on by the gap left until the rounded shape itself touches, so it slides past corners
+// its bounding box would have caught on. the normal comes from the overlap check
+pub fn sweep_capsule_box(
+    capsule_pos: Vec2,
+    half_height: f32,
+    radius: f32,
+    displacement: Vec2,
+    box_pos: Vec2,
+    box_size: Vec2,
+) -> Option<(f32, Contact)> {
+    let extents = Vec2::new(radius, half_height + radius);
+    let bounds_overlap = ((capsule_pos - box_pos).abs())
+        .cmplt(extents + box_size / 2.0)
+        .all();
+    let mut t = match sweep_aabb(capsule_pos, extents * 2.0, displacement, box_pos, box_size) {
+        Some((_, t)) => t,
+        // the bounding box can overlap a corner the capsule isn't touching yet
+        None if bounds_overlap => 0.0,
+        None => return None,
+    };
+
+    let distance = displacement.length();
+    let mut steps = 0;
+    loop {
+        let gap = capsule_box_gap(
+            capsule_pos + displacement * t,
+            half_height,
+            radius,
+            box_pos,
+            box_size,
+        );
+        if gap <= SWEEP_SKIN {
+            // already inside is left to the overlap checks
+            if gap < -SWEEP_SKIN {
+                return None;
+            }
+            break;
+        }
+        steps += 1;
+        t += gap / distance;
+        if t > 1.0 || steps > MAX_SWEEP_STEPS {
+            return None;
+        }
+    }
+
+    let touching = contact_capsule_box(
+        capsule_pos + displacement * t,
+        half_height,
+        radius + 2.0 * SWEEP_SKIN,
+        box_pos,
+        box_size,
+    )?;
+    // moving along or away from the surface isn't a hit
+    if displacement.dot(touching.normal) >= 0.0 {
+        return None;
+    }
+    Some((
+        t,
+        Contact {
+            depth: 0.0,
+            ..touching
+        },
+    ))
+}
+
+// returns the fractions of the displacement where the point enters and exits the slab
+fn sweep_axis(origin: f32, displacement: f32, min: f32, max: f32) -> Option<(f32, f32)> {
+    if displacement == 0.0 {
+        if origin > min && origin < max {
+            Some((f32::NEG_INFINITY, f32::INFINITY))
+        } else {
+            None
+        }
+    } else {
+        let t_min = (min - origin) / displacement;
+        let t_max = (max - origin) / displacement;
+        Some((t_min.min(t_max), t_min.max(t_max)))
+    }
+}
+
 #[derive(Clone)]
 struct RaySolution(Collision, f32);
 
@@ -164,9 +820,15 @@ fn c_max(a: &RaySolution, b: &RaySolution) -> RaySolution {
     }
 }
 
+// side of the box the ray hits and how far along the ray the hit is
 // algorithm adapted from here https://tavianator.com/2011/ray_box.html
 // may not handle collisions with corners correctly
-fn raycast_to_box(ray_pos: Vec2, ray: Vec2, box_pos: Vec2, box_size: Vec2) -> Option<Collision> {
+fn raycast_to_box_solution(
+    ray_pos: Vec2,
+    ray: Vec2,
+    box_pos: Vec2,
+    box_size: Vec2,
+) -> Option<RaySolution> {
     // calculate vectors to corners of box from ray origin
     let bottom_left = box_pos - box_size / 2.0 - ray_pos; // bottom left
     let top_right = box_pos + box_size / 2.0 - ray_pos; // top right
@@ -194,151 +856,697 @@ fn raycast_to_box(ray_pos: Vec2, ray: Vec2, box_pos: Vec2, box_size: Vec2) -> Op
         None
     } else if tmin.1 >= 0.0 {
         // ray collides from outside box
-        Some(tmin.0)
+        Some(tmin)
     } else {
         // ray collides from inside box
-        Some(tmax.0)
+        Some(tmax)
     }
 }
 
-#[derive(Component)]
-pub struct Collisions(pub Vec<CollisionData>);
+pub fn contact_ray_box(ray_pos: Vec2, ray: Vec2, box_pos: Vec2, box_size: Vec2) -> Option<Contact> {
+    let RaySolution(direction, t) = raycast_to_box_solution(ray_pos, ray, box_pos, box_size)?;
+    Some(Contact {
+        normal: direction.normal(),
+        direction,
+        // the part of the ray that ended up past the side it hit
+        depth: ray.length() - t,
+        point: ray_pos + ray.normalize() * t,
+    })
+}
 
-impl Hurtbox {
-    pub fn check_collision(
-        self: &Self,
-        hurt_position: &Position,
-        hitbox: &Hitbox,
-        hitbox_position: &Position,
-        hit_entity: Entity,
-    ) -> Option<CollisionData> {
-        match (&self.shape, &hitbox.shape) {
-            (&CollisionShape::Rect(hurt_size), &CollisionShape::Rect(hit_size)) => {
-                if let Some(direction) = collide_aabb(
-                    hurt_position.0.extend(0.0),
-                    hurt_size,
-                    hitbox_position.0.extend(0.0),
-                    hit_size,
-                ) {
-                    return match (&self.col_type, &hitbox.col_type) {
-                        (&ColliderType::Player, &ColliderType::Ground) => Some(CollisionData {
-                            entity: hit_entity,
-                            direction,
-                            collision_type: CollisionType::PlayerHitsGround {
-                                ground_pos: hitbox_position.0.clone(),
-                                ground_size: hit_size.clone(),
-                            },
-                        }),
-                        _ => None,
-                    };
-                }
-                return None;
-            }
-            (&CollisionShape::Ray(ray), &CollisionShape::Rect(hit_size)) => {
-                if let Some(direction) =
-                    raycast_to_box(hurt_position.0, ray, hitbox_position.0, hit_size)
-                {
-                    return match (&self.col_type, &hitbox.col_type) {
-                        (&ColliderType::PlayerRay, &ColliderType::Ground) => Some(CollisionData {
-                            entity: hit_entity,
-                            direction,
-                            collision_type: CollisionType::PlayerRayHitsGround {
-                                ground_pos: hitbox_position.0.clone(),
-                                ground_size: hit_size.clone(),
-                            },
-                        }),
-                        _ => None,
-                    };
-                }
-                return None;
-            }
-            // being lazy and not supporting all collision types
-            _ => return None,
-        }
+// finds where the segments p -> p + r and q -> q + s cross, ignoring parallel segments
+pub fn contact_ray_ray(p: Vec2, r: Vec2, q: Vec2, s: Vec2) -> Option<Contact> {
+    let cross = |a: Vec2, b: Vec2| a.x * b.y - a.y * b.x;
+
+    let r_cross_s = cross(r, s);
+    if r_cross_s == 0.0 {
+        return None;
+    }
+    let t = cross(q - p, s) / r_cross_s;
+    let u = cross(q - p, r) / r_cross_s;
+    if t < 0.0 || t > 1.0 || u < 0.0 || u > 1.0 {
+        return None;
     }
-}
 
-pub fn check_collisions(
-    mut hurtboxes: Query<(&Hurtbox, &Position, &mut Collisions)>,
-    hitboxes: Query<(Entity, &Hitbox, &Position)>,
-) {
-    for (hurtbox, hurt_position, mut collisions) in hurtboxes.iter_mut() {
-        for (hit_entity, hitbox, hitbox_position) in hitboxes.iter() {
-            if let Some(collision) =
-                hurtbox.check_collision(hurt_position, hitbox, hitbox_position, hit_entity)
-            {
-                collisions.0.push(collision);
-            }
-        }
+    // normal of the hit segment facing back towards where the ray came from
+    let mut normal = Vec2::new(-s.y, s.x).normalize();
+    if normal.dot(r) > 0.0 {
+        normal = -normal;
     }
+
+    Some(Contact {
+        direction: Collision::from_normal(normal),
+        // how far the end of the ray is past the segment along the normal
+        depth: (1.0 - t) * -r.dot(normal),
+        normal,
+        point: p + r * t,
+    })
 }
 
-pub fn clean_up_collisions(mut q: Query<&mut Collisions>) {
-    for mut c in q.iter_mut() {
-        c.0.clear();
+// closest points between the vertical segments a_pos +- a_half_height and b_pos +- b_half_height
+fn closest_points_vertical_segments(
+    a_pos: Vec2,
+    a_half_height: f32,
+    b_pos: Vec2,
+    b_half_height: f32,
+) -> (Vec2, Vec2) {
+    let overlap_min = (a_pos.y - a_half_height).max(b_pos.y - b_half_height);
+    let overlap_max = (a_pos.y + a_half_height).min(b_pos.y + b_half_height);
+
+    let (a_y, b_y) = if overlap_min <= overlap_max {
+        // the segments are side by side, so any height they share is closest
+        let y = (overlap_min + overlap_max) / 2.0;
+        (y, y)
+    } else if a_pos.y > b_pos.y {
+        (a_pos.y - a_half_height, b_pos.y + b_half_height)
+    } else {
+        (a_pos.y + a_half_height, b_pos.y - b_half_height)
+    };
+
+    (Vec2::new(a_pos.x, a_y), Vec2::new(b_pos.x, b_y))
+}
+
+pub fn contact_capsules(
+    a_pos: Vec2,
+    a_half_height: f32,
+    a_radius: f32,
+    b_pos: Vec2,
+    b_half_height: f32,
+    b_radius: f32,
+) -> Option<Contact> {
+    let (a_closest, b_closest) =
+        closest_points_vertical_segments(a_pos, a_half_height, b_pos, b_half_height);
+    let offset = a_closest - b_closest;
+    let distance = offset.length();
+    if distance >= a_radius + b_radius {
+        return None;
     }
+
+    // centers on top of each other have no preferred direction, so push up
+    let normal = if distance > 0.0 {
+        offset / distance
+    } else {
+        Vec2::new(0.0, 1.0)
+    };
+
+    Some(Contact {
+        direction: Collision::from_normal(normal),
+        depth: a_radius + b_radius - distance,
+        normal,
+        point: b_closest + normal * b_radius,
+    })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+pub fn contact_capsule_box(
+    capsule_pos: Vec2,
+    half_height: f32,
+    radius: f32,
+    box_pos: Vec2,
+    box_size: Vec2,
+) -> Option<Contact> {
+    let box_min = box_pos - box_size / 2.0;
+    let box_max = box_pos + box_size / 2.0;
 
-    /*
-    *---------->
-        ____
-        |  |
-        |  |
-        |__|
-    */
-    #[test]
-    fn ray_misses_box() {
-        let ray_pos = Vec2::new(0.0, 0.0);
-        let ray = Vec2::new(5.0, 0.0);
-        let hitbox_pos = Vec2::new(10.0, 10.0);
-        let hitbox_size = Vec2::new(1.0, 1.0);
+    // point on the capsule's segment closest to the box
+    let (segment_point, _) =
+        closest_points_vertical_segments(capsule_pos, half_height, box_pos, box_size.y / 2.0);
+    let box_point = segment_point.max(box_min).min(box_max);
 
-        let result = raycast_to_box(ray_pos, ray, hitbox_pos, hitbox_size);
-        assert!(result.is_none());
+    let offset = segment_point - box_point;
+    let distance = offset.length();
+    if distance >= radius {
+        return None;
     }
 
-    /*
-            ____
-    *---->  |  |
-            |__|
-    */
+    if distance > 0.0 {
+        let normal = offset / distance;
+        Some(Contact {
+            direction: Collision::from_normal(normal),
+            depth: radius - distance,
+            normal,
+            point: box_point,
+        })
+    } else {
+        // the segment is inside the box, so fall back to pushing out the capsule's bounding box
+        let extents = Vec2::new(radius, half_height + radius);
+        contact_aabb(capsule_pos, extents * 2.0, box_pos, box_size)
+    }
+}
 
-    #[test]
-    fn ray_ends_before_box() {
-        let ray_pos = Vec2::new(0.0, 0.0);
-        let ray = Vec2::new(5.0, 0.0);
-        let hitbox_pos = Vec2::new(6.0, 0.0);
-        let hitbox_size = Vec2::new(1.0, 1.0);
+// returns the normal and distance along the ray of where it enters the circle. rays starting
+// inside the circle are ignored.
+fn raycast_to_circle(ray_pos: Vec2, ray: Vec2, center: Vec2, radius: f32) -> Option<(Vec2, f32)> {
+    let direction = ray.normalize();
+    let to_ray = ray_pos - center;
 
-        let result = raycast_to_box(ray_pos, ray, hitbox_pos, hitbox_size);
-        assert!(result.is_none());
+    let b = to_ray.dot(direction);
+    let c = to_ray.length_squared() - radius * radius;
+    let discriminant = b * b - c;
+    if c <= 0.0 || discriminant < 0.0 {
+        return None;
     }
 
-    /*
-    ____
-    |  |  *---->
-    |__|
-    */
+    let t = -b - discriminant.sqrt();
+    if t < 0.0 || t >= ray.length() {
+        return None;
+    }
+    Some(((ray_pos + direction * t - center) / radius, t))
+}
 
-    #[test]
-    fn ray_starts_after_box() {
-        let ray_pos = Vec2::new(7.0, 0.0);
-        let ray = Vec2::new(5.0, 0.0);
-        let hitbox_pos = Vec2::new(6.0, 0.0);
-        let hitbox_size = Vec2::new(1.0, 1.0);
+// a capsule is a box with a circle on each end, so the ray enters at the first of them it hits
+pub fn contact_ray_capsule(
+    ray_pos: Vec2,
+    ray: Vec2,
+    capsule_pos: Vec2,
+    half_height: f32,
+    radius: f32,
+) -> Option<Contact> {
+    let top = Vec2::new(0.0, half_height);
+    let mut hit = raycast_to_circle(ray_pos, ray, capsule_pos + top, radius);
+    if let Some((normal, t)) = raycast_to_circle(ray_pos, ray, capsule_pos - top, radius) {
+        if hit.map_or(true, |(_, hit_t)| t < hit_t) {
+            hit = Some((normal, t));
+        }
+    }
 
-        let result = raycast_to_box(ray_pos, ray, hitbox_pos, hitbox_size);
-        assert!(result.is_none());
+    let middle_size = Vec2::new(radius * 2.0, half_height * 2.0);
+    let (middle_min, middle_max) = CollisionShape::Rect(middle_size).bounds(capsule_pos);
+    let starts_in_middle = ray_pos.x > middle_min.x
+        && ray_pos.x < middle_max.x
+        && ray_pos.y > middle_min.y
+        && ray_pos.y < middle_max.y;
+    if half_height > 0.0 && !starts_in_middle {
+        if let Some(RaySolution(side, t)) =
+            raycast_to_box_solution(ray_pos, ray, capsule_pos, middle_size)
+        {
+            if hit.map_or(true, |(_, hit_t)| t < hit_t) {
+                hit = Some((side.normal(), t));
+            }
+        }
     }
 
-    // checks case where ray pierces box
-    /*
-        ______
-        |    |
+    let (normal, t) = hit?;
+    Some(Contact {
+        direction: Collision::from_normal(normal),
+        depth: ray.length() - t,
+        normal,
+        point: ray_pos + ray.normalize() * t,
+    })
+}
+
+fn slope_surface_height(x: f32, slope_pos: Vec2, slope_size: Vec2, rises_right: bool) -> f32 {
+    let slope_min = slope_pos - slope_size / 2.0;
+    let t = ((x - slope_min.x) / slope_size.x).max(0.0).min(1.0);
+    let t = if rises_right { t } else { 1.0 - t };
+    slope_min.y + slope_size.y * t
+}
+
+// shapes stand on slopes by the middle of their bottom edge, and are pushed straight up so they
+// don't slide down the surface
+pub fn contact_slope(
+    shape: &CollisionShape,
+    shape_pos: Vec2,
+    slope_pos: Vec2,
+    slope_size: Vec2,
+    rises_right: bool,
+) -> Option<Contact> {
+    let (shape_min, shape_max) = shape.bounds(shape_pos);
+    let slope_min = slope_pos - slope_size / 2.0;
+    let slope_max = slope_pos + slope_size / 2.0;
+    if shape_min.x >= slope_max.x
+        || shape_max.x <= slope_min.x
+        || shape_min.y >= slope_max.y
+        || shape_max.y <= slope_min.y
+    {
+        return None;
+    }
+
+    let foot = Vec2::new(shape_pos.x, shape_min.y);
+    let past_low_end = if rises_right {
+        foot.x < slope_min.x
+    } else {
+        foot.x > slope_max.x
+    };
+    if past_low_end {
+        // whatever the slope runs down into will hold the shape up
+        return None;
+    }
+
+    if foot.x >= slope_min.x && foot.x <= slope_max.x && foot.y >= slope_min.y {
+        let surface = slope_surface_height(foot.x, slope_pos, slope_size, rises_right);
+        if foot.y >= surface {
+            return None;
+        }
+        return Some(Contact {
+            direction: Collision::Top,
+            depth: surface - foot.y,
+            normal: Vec2::new(0.0, 1.0),
+            point: Vec2::new(foot.x, surface),
+        });
+    }
+
+    // past the high end or under the slope it is as solid as its box
+    contact_aabb(shape_pos, shape_max - shape_min, slope_pos, slope_size)
+}
+
+pub fn contact_ray_slope(
+    ray_pos: Vec2,
+    ray: Vec2,
+    slope_pos: Vec2,
+    slope_size: Vec2,
+    rises_right: bool,
+) -> Option<Contact> {
+    let slope_min = slope_pos - slope_size / 2.0;
+    let slope_max = slope_pos + slope_size / 2.0;
+    let (low, high_top, high_bottom) = if rises_right {
+        (slope_min, slope_max, Vec2::new(slope_max.x, slope_min.y))
+    } else {
+        (
+            Vec2::new(slope_max.x, slope_min.y),
+            Vec2::new(slope_min.x, slope_max.y),
+            slope_min,
+        )
+    };
+
+    // the ray hits whichever side of the triangle it crosses first
+    let sides = [
+        (low, high_top - low),
+        (high_top, high_bottom - high_top),
+        (high_bottom, low - high_bottom),
+    ];
+    let mut closest: Option<Contact> = None;
+    for &(start, side) in sides.iter() {
+        if let Some(contact) = contact_ray_ray(ray_pos, ray, start, side) {
+            let distance = (contact.point - ray_pos).length_squared();
+            if closest
+                .as_ref()
+                .map_or(true, |c| distance < (c.point - ray_pos).length_squared())
+            {
+                closest = Some(contact);
+            }
+        }
+    }
+    closest
+}
+
+// narrow phase for every pair of shapes, from the point of view of the hurt shape
+pub fn contact_shapes(
+    hurt_shape: &CollisionShape,
+    hurt_pos: Vec2,
+    hit_shape: &CollisionShape,
+    hit_pos: Vec2,
+) -> Option<Contact> {
+    match (hurt_shape, hit_shape) {
+        // slopes against each other are only as precise as their boxes
+        (
+            &CollisionShape::Slope {
+                size: hurt_size, ..
+            },
+            &CollisionShape::Slope { size: hit_size, .. },
+        ) => contact_aabb(hurt_pos, hurt_size, hit_pos, hit_size),
+        (&CollisionShape::Ray(ray), &CollisionShape::Slope { size, rises_right }) => {
+            contact_ray_slope(hurt_pos, ray, hit_pos, size, rises_right)
+        }
+        (&CollisionShape::Slope { size, rises_right }, &CollisionShape::Ray(ray)) => {
+            contact_ray_slope(hit_pos, ray, hurt_pos, size, rises_right).map(Contact::flip)
+        }
+        (_, &CollisionShape::Slope { size, rises_right }) => {
+            contact_slope(hurt_shape, hurt_pos, hit_pos, size, rises_right)
+        }
+        (&CollisionShape::Slope { size, rises_right }, _) => {
+            contact_slope(hit_shape, hit_pos, hurt_pos, size, rises_right).map(Contact::flip)
+        }
+        (&CollisionShape::Rect(hurt_size), &CollisionShape::Rect(hit_size)) => {
+            contact_aabb(hurt_pos, hurt_size, hit_pos, hit_size)
+        }
+        (&CollisionShape::Ray(ray), &CollisionShape::Rect(hit_size)) => {
+            contact_ray_box(hurt_pos, ray, hit_pos, hit_size)
+        }
+        (&CollisionShape::Rect(hurt_size), &CollisionShape::Ray(ray)) => {
+            contact_ray_box(hit_pos, ray, hurt_pos, hurt_size).map(Contact::flip)
+        }
+        (&CollisionShape::Ray(hurt_ray), &CollisionShape::Ray(hit_ray)) => {
+            contact_ray_ray(hurt_pos, hurt_ray, hit_pos, hit_ray)
+        }
+        _ => match (hurt_shape.capsule(), hit_shape.capsule()) {
+            (Some((hurt_half_height, hurt_radius)), Some((hit_half_height, hit_radius))) => {
+                contact_capsules(
+                    hurt_pos,
+                    hurt_half_height,
+                    hurt_radius,
+                    hit_pos,
+                    hit_half_height,
+                    hit_radius,
+                )
+            }
+            (Some((half_height, radius)), None) => match hit_shape {
+                &CollisionShape::Rect(hit_size) => {
+                    contact_capsule_box(hurt_pos, half_height, radius, hit_pos, hit_size)
+                }
+                &CollisionShape::Ray(ray) => {
+                    contact_ray_capsule(hit_pos, ray, hurt_pos, half_height, radius)
+                        .map(Contact::flip)
+                }
+                _ => None,
+            },
+            (None, Some((half_height, radius))) => match hurt_shape {
+                &CollisionShape::Rect(hurt_size) => {
+                    contact_capsule_box(hit_pos, half_height, radius, hurt_pos, hurt_size)
+                        .map(Contact::flip)
+                }
+                &CollisionShape::Ray(ray) => {
+                    contact_ray_capsule(hurt_pos, ray, hit_pos, half_height, radius)
+                }
+                _ => None,
+            },
+            (None, None) => None,
+        },
+    }
+}
+
+#[derive(Component)]
+pub struct Collisions(pub Vec<CollisionData>);
+
+impl Hurtbox {
+    pub fn check_collision(
+        self: &Self,
+        hurt_position: &Position,
+        hitbox: &Hitbox,
+        hitbox_position: &Position,
+        hit_entity: Entity,
+        matrix: &CollisionMatrix,
+    ) -> Option<CollisionData> {
+        if !matrix.interacts(self.col_type, hitbox.col_type) {
+            return None;
+        }
+
+        let contact = contact_shapes(
+            &self.shape,
+            hurt_position.0,
+            &hitbox.shape,
+            hitbox_position.0,
+        )?;
+
+        Some(self.collision_data(contact, hitbox, hit_entity))
+    }
+
+    pub fn sweep_collision(
+        self: &Self,
+        hurt_position: &Position,
+        displacement: Vec2,
+        hitbox: &Hitbox,
+        hitbox_position: &Position,
+        hit_entity: Entity,
+        matrix: &CollisionMatrix,
+    ) -> Option<(f32, CollisionData)> {
+        if !matrix.interacts(self.col_type, hitbox.col_type) || !hitbox.col_type.is_solid() {
+            return None;
+        }
+
+        let hit_size = match hitbox.shape {
+            CollisionShape::Rect(hit_size) => hit_size,
+            // thin rays and rounded hitboxes are left to the overlap checks
+            _ => return None,
+        };
+
+        let (t, contact) = match self.shape {
+            CollisionShape::Rect(hurt_size) => {
+                let (direction, t) = sweep_aabb(
+                    hurt_position.0,
+                    hurt_size,
+                    displacement,
+                    hitbox_position.0,
+                    hit_size,
+                )?;
+                // the sweep stops right as the boxes touch, so there is nothing to push out
+                let normal = direction.normal();
+                let contact = Contact {
+                    point: hurt_position.0 + displacement * t - normal * hurt_size / 2.0,
+                    normal,
+                    direction,
+                    depth: 0.0,
+                };
+                (t, contact)
+            }
+            _ => {
+                let (half_height, radius) = self.shape.capsule()?;
+                sweep_capsule_box(
+                    hurt_position.0,
+                    half_height,
+                    radius,
+                    displacement,
+                    hitbox_position.0,
+                    hit_size,
+                )?
+            }
+        };
+        if hitbox.col_type.is_one_way() && !matches!(contact.direction, Collision::Top) {
+            return None;
+        }
+        Some((t, self.collision_data(contact, hitbox, hit_entity)))
+    }
+
+    fn collision_data(
+        self: &Self,
+        contact: Contact,
+        hitbox: &Hitbox,
+        hit_entity: Entity,
+    ) -> CollisionData {
+        CollisionData {
+            entity: hit_entity,
+            direction: contact.direction,
+            depth: contact.depth,
+            normal: contact.normal,
+            point: contact.point,
+            collision_type: CollisionType {
+                hit: hitbox.col_type,
+                hit_shape: hitbox.shape,
+            },
+        }
+    }
+}
+
+// uniform grid of the hitboxes so collision checks only need to look at nearby hitboxes
+pub struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<Entity>>,
+    entity_cells: HashMap<Entity, ((i32, i32), (i32, i32))>,
+}
+
+impl Default for SpatialHash {
+    fn default() -> SpatialHash {
+        SpatialHash::new(64.0)
+    }
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> SpatialHash {
+        SpatialHash {
+            cell_size,
+            cells: HashMap::default(),
+            entity_cells: HashMap::default(),
+        }
+    }
+
+    fn cell(&self, point: Vec2) -> (i32, i32) {
+        let cell = (point / self.cell_size).floor();
+        (cell.x as i32, cell.y as i32)
+    }
+
+    pub fn insert(&mut self, entity: Entity, min: Vec2, max: Vec2) {
+        self.remove(entity);
+
+        let (min_cell, max_cell) = (self.cell(min), self.cell(max));
+        for x in min_cell.0..=max_cell.0 {
+            for y in min_cell.1..=max_cell.1 {
+                self.cells.entry((x, y)).or_default().push(entity);
+            }
+        }
+        self.entity_cells.insert(entity, (min_cell, max_cell));
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some((min_cell, max_cell)) = self.entity_cells.remove(&entity) {
+            for x in min_cell.0..=max_cell.0 {
+                for y in min_cell.1..=max_cell.1 {
+                    if let Some(entities) = self.cells.get_mut(&(x, y)) {
+                        entities.retain(|&e| e != entity);
+                    }
+                }
+            }
+        }
+    }
+
+    // returns every entity sharing a cell with the box, without duplicates
+    pub fn query(&self, min: Vec2, max: Vec2) -> Vec<Entity> {
+        let (min_cell, max_cell) = (self.cell(min), self.cell(max));
+        let mut entities = Vec::new();
+        for x in min_cell.0..=max_cell.0 {
+            for y in min_cell.1..=max_cell.1 {
+                if let Some(cell) = self.cells.get(&(x, y)) {
+                    entities.extend_from_slice(cell);
+                }
+            }
+        }
+        entities.sort_unstable();
+        entities.dedup();
+        entities
+    }
+}
+
+pub fn update_spatial_hash(
+    mut spatial_hash: ResMut<SpatialHash>,
+    hitboxes: Query<(Entity, &Hitbox, &Position), Or<(Changed<Hitbox>, Changed<Position>)>>,
+    removed: RemovedComponents<Hitbox>,
+) {
+    for entity in removed.iter() {
+        spatial_hash.remove(entity);
+    }
+    for (entity, hitbox, position) in hitboxes.iter() {
+        let (min, max) = hitbox.shape.bounds(position.0);
+        spatial_hash.insert(entity, min, max);
+    }
+}
+
+// how far into a one-way hitbox a body can already be and still land on it
+const ONE_WAY_SLOP: f32 = 0.5;
+
+// one-way hitboxes only hold up bodies coming down onto their top face. anything deeper than the
+// last step's fall was already inside, like a body jumping up through the platform
+pub fn lands_on_one_way(direction: &Collision, depth: f32, velocity_y: f32, dt: f32) -> bool {
+    match direction {
+        Collision::Top => velocity_y <= 0.0 && depth <= -velocity_y * dt + ONE_WAY_SLOP,
+        _ => false,
+    }
+}
+
+pub fn check_collisions(
+    mut hurtboxes: Query<(&Hurtbox, &Position, &mut Collisions)>,
+    hitboxes: Query<(&Hitbox, &Position)>,
+    spatial_hash: Res<SpatialHash>,
+    collision_matrices: Res<Assets<CollisionMatrix>>,
+    collision_matrix_handle: Res<CollisionMatrixHandle>,
+) {
+    let matrix = match collision_matrices.get(&collision_matrix_handle.0) {
+        Some(matrix) => matrix,
+        None => return,
+    };
+
+    for (hurtbox, hurt_position, mut collisions) in hurtboxes.iter_mut() {
+        let (min, max) = hurtbox.shape.bounds(hurt_position.0);
+        for hit_entity in spatial_hash.query(min, max) {
+            let (hitbox, hitbox_position) = match hitboxes.get(hit_entity) {
+                Ok(hitbox) => hitbox,
+                Err(_) => continue,
+            };
+            if let Some(collision) =
+                hurtbox.check_collision(hurt_position, hitbox, hitbox_position, hit_entity, matrix)
+            {
+                collisions.0.push(collision);
+            }
+        }
+    }
+}
+
+pub fn clean_up_collisions(mut q: Query<&mut Collisions>) {
+    for mut c in q.iter_mut() {
+        c.0.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{Action, Actions};
+    use crate::testing;
+
+    struct Ticks(u32);
+
+    fn count_ticks(mut ticks: ResMut<Ticks>) {
+        ticks.0 += 1;
+    }
+
+    #[test]
+    fn physics_stage_caps_the_ticks_caught_up_in_one_frame() {
+        let mut world = World::default();
+        world.insert_resource(LoaderState::Loaded);
+        world.insert_resource(Ticks(0));
+        // a long hitch's worth of time
+        world.insert_resource(PhysicsTimestep {
+            accumulator: 2.0,
+            ..Default::default()
+        });
+        let mut stage = PhysicsStage {
+            tick: SystemStage::single_threaded().with_system(count_ticks),
+            sub_step: SystemStage::single_threaded(),
+            after_sub_steps: SystemStage::single_threaded(),
+        };
+
+        stage.run(&mut world);
+        assert_eq!(
+            world.get_resource::<Ticks>().unwrap().0,
+            MAX_TICKS_PER_FRAME
+        );
+        let timestep = world.get_resource::<PhysicsTimestep>().unwrap();
+        assert!(timestep.accumulator < timestep.dt as f64);
+    }
+
+    /*
+    *---------->
+        ____
+        |  |
+        |  |
+        |__|
+    */
+    #[test]
+    fn ray_misses_box() {
+        let ray_pos = Vec2::new(0.0, 0.0);
+        let ray = Vec2::new(5.0, 0.0);
+        let hitbox_pos = Vec2::new(10.0, 10.0);
+        let hitbox_size = Vec2::new(1.0, 1.0);
+
+        let result = contact_ray_box(ray_pos, ray, hitbox_pos, hitbox_size);
+        assert!(result.is_none());
+    }
+
+    /*
+            ____
+    *---->  |  |
+            |__|
+    */
+
+    #[test]
+    fn ray_ends_before_box() {
+        let ray_pos = Vec2::new(0.0, 0.0);
+        let ray = Vec2::new(5.0, 0.0);
+        let hitbox_pos = Vec2::new(6.0, 0.0);
+        let hitbox_size = Vec2::new(1.0, 1.0);
+
+        let result = contact_ray_box(ray_pos, ray, hitbox_pos, hitbox_size);
+        assert!(result.is_none());
+    }
+
+    /*
+    ____
+    |  |  *---->
+    |__|
+    */
+
+    #[test]
+    fn ray_starts_after_box() {
+        let ray_pos = Vec2::new(7.0, 0.0);
+        let ray = Vec2::new(5.0, 0.0);
+        let hitbox_pos = Vec2::new(6.0, 0.0);
+        let hitbox_size = Vec2::new(1.0, 1.0);
+
+        let result = contact_ray_box(ray_pos, ray, hitbox_pos, hitbox_size);
+        assert!(result.is_none());
+    }
+
+    // checks case where ray pierces box
+    /*
+        ______
+        |    |
      *--X----X-->
         |    |
         |____|
@@ -350,8 +1558,8 @@ mod tests {
         let hitbox_pos = Vec2::new(3.0, 0.0);
         let hitbox_size = Vec2::new(2.0, 4.0);
 
-        let result = raycast_to_box(ray_pos, ray, hitbox_pos, hitbox_size);
-        let collision = result.unwrap();
+        let result = contact_ray_box(ray_pos, ray, hitbox_pos, hitbox_size);
+        let collision = result.unwrap().direction;
 
         match collision {
             Collision::Left => assert!(true),
@@ -374,8 +1582,8 @@ mod tests {
         let hitbox_pos = Vec2::new(-3.0, 0.0);
         let hitbox_size = Vec2::new(2.0, 4.0);
 
-        let result = raycast_to_box(ray_pos, ray, hitbox_pos, hitbox_size);
-        let collision = result.unwrap();
+        let result = contact_ray_box(ray_pos, ray, hitbox_pos, hitbox_size);
+        let collision = result.unwrap().direction;
 
         match collision {
             Collision::Right => assert!(true),
@@ -397,8 +1605,8 @@ mod tests {
         let hitbox_pos = Vec2::new(6.0, 0.0);
         let hitbox_size = Vec2::new(3.0, 4.0);
 
-        let result = raycast_to_box(ray_pos, ray, hitbox_pos, hitbox_size);
-        let collision = result.unwrap();
+        let result = contact_ray_box(ray_pos, ray, hitbox_pos, hitbox_size);
+        let collision = result.unwrap().direction;
 
         match collision {
             Collision::Left => assert!(true),
@@ -420,8 +1628,8 @@ mod tests {
         let hitbox_pos = Vec2::new(0.0, 0.0);
         let hitbox_size = Vec2::new(3.0, 4.0);
 
-        let result = raycast_to_box(ray_pos, ray, hitbox_pos, hitbox_size);
-        let collision = result.unwrap();
+        let result = contact_ray_box(ray_pos, ray, hitbox_pos, hitbox_size);
+        let collision = result.unwrap().direction;
 
         match collision {
             Collision::Right => assert!(true),
@@ -443,7 +1651,7 @@ mod tests {
         let hitbox_pos = Vec2::new(0.0, 0.0);
         let hitbox_size = Vec2::new(3.0, 4.0);
 
-        let result = raycast_to_box(ray_pos, ray, hitbox_pos, hitbox_size);
+        let result = contact_ray_box(ray_pos, ray, hitbox_pos, hitbox_size);
         assert!(result.is_none());
     }
 
@@ -454,8 +1662,8 @@ mod tests {
         let hitbox_pos = Vec2::new(0.0, -2.0);
         let hitbox_size = Vec2::new(4.0, 2.0);
 
-        let result = raycast_to_box(ray_pos, ray, hitbox_pos, hitbox_size);
-        let collision = result.unwrap();
+        let result = contact_ray_box(ray_pos, ray, hitbox_pos, hitbox_size);
+        let collision = result.unwrap().direction;
 
         match collision {
             Collision::Top => assert!(true),
@@ -470,12 +1678,1265 @@ mod tests {
         let hitbox_pos = Vec2::new(0.0, 2.0);
         let hitbox_size = Vec2::new(4.0, 2.0);
 
-        let result = raycast_to_box(ray_pos, ray, hitbox_pos, hitbox_size);
-        let collision = result.unwrap();
+        let result = contact_ray_box(ray_pos, ray, hitbox_pos, hitbox_size);
+        let collision = result.unwrap().direction;
+
+        match collision {
+            Collision::Bottom => assert!(true),
+            _ => assert!(false),
+        }
+    }
+
+    // checks case where a fast body would end up below a thin box after one step
+    /*
+        ____
+        |  |
+        |__|
+         |
+      ___V___
+      |_____|
+
+        ____
+        |  |
+        |__|
+    */
+    #[test]
+    fn sweep_detects_tunneling_from_top() {
+        let a_pos = Vec2::new(0.0, 10.0);
+        let a_size = Vec2::new(2.0, 2.0);
+        let displacement = Vec2::new(0.0, -20.0);
+        let b_pos = Vec2::new(0.0, 0.0);
+        let b_size = Vec2::new(10.0, 1.0);
+
+        let (collision, t) = sweep_aabb(a_pos, a_size, displacement, b_pos, b_size).unwrap();
+
+        match collision {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        // stops when the bottom of a touches the top of b
+        assert!((a_pos.y + displacement.y * t - 1.5).abs() < f32::EPSILON);
+    }
+
+    /*
+        ____    ______
+        |  |--->|    |
+        |__|    |____|
+    */
+    #[test]
+    fn sweep_detects_collision_from_left() {
+        let a_pos = Vec2::new(0.0, 0.0);
+        let a_size = Vec2::new(2.0, 2.0);
+        let displacement = Vec2::new(10.0, 0.0);
+        let b_pos = Vec2::new(6.0, 0.0);
+        let b_size = Vec2::new(2.0, 4.0);
+
+        let (collision, t) = sweep_aabb(a_pos, a_size, displacement, b_pos, b_size).unwrap();
+
+        match collision {
+            Collision::Left => assert!(true),
+            _ => assert!(false),
+        }
+        assert!((t - 0.4).abs() < f32::EPSILON);
+    }
+
+    /*
+        ______
+        |    |
+        |____|
+          ^
+        __|_
+        |  |
+        |__|
+    */
+    #[test]
+    fn sweep_detects_collision_from_bottom() {
+        let a_pos = Vec2::new(0.0, 0.0);
+        let a_size = Vec2::new(2.0, 2.0);
+        let displacement = Vec2::new(0.0, 10.0);
+        let b_pos = Vec2::new(0.0, 6.0);
+        let b_size = Vec2::new(4.0, 2.0);
+
+        let (collision, _) = sweep_aabb(a_pos, a_size, displacement, b_pos, b_size).unwrap();
 
         match collision {
             Collision::Bottom => assert!(true),
             _ => assert!(false),
         }
     }
+
+    /*
+        ____      ______
+        |  |->    |    |
+        |__|      |____|
+    */
+    #[test]
+    fn sweep_stops_before_box() {
+        let a_pos = Vec2::new(0.0, 0.0);
+        let a_size = Vec2::new(2.0, 2.0);
+        let displacement = Vec2::new(2.0, 0.0);
+        let b_pos = Vec2::new(6.0, 0.0);
+        let b_size = Vec2::new(2.0, 4.0);
+
+        let result = sweep_aabb(a_pos, a_size, displacement, b_pos, b_size);
+        assert!(result.is_none());
+    }
+
+    /*
+        ____
+        |  |--->
+        |__|
+                ______
+                |    |
+                |____|
+    */
+    #[test]
+    fn sweep_passes_by_box() {
+        let a_pos = Vec2::new(0.0, 0.0);
+        let a_size = Vec2::new(2.0, 2.0);
+        let displacement = Vec2::new(10.0, 0.0);
+        let b_pos = Vec2::new(6.0, -5.0);
+        let b_size = Vec2::new(2.0, 2.0);
+
+        let result = sweep_aabb(a_pos, a_size, displacement, b_pos, b_size);
+        assert!(result.is_none());
+    }
+
+    // resting contact with gravity pulling down reports the top immediately
+    #[test]
+    fn sweep_detects_resting_contact() {
+        let a_pos = Vec2::new(0.0, 2.0);
+        let a_size = Vec2::new(2.0, 2.0);
+        let displacement = Vec2::new(3.0, -1.0);
+        let b_pos = Vec2::new(0.0, 0.0);
+        let b_size = Vec2::new(10.0, 2.0);
+
+        let (collision, t) = sweep_aabb(a_pos, a_size, displacement, b_pos, b_size).unwrap();
+
+        match collision {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        assert_eq!(t, 0.0);
+    }
+
+    // sliding along the top of a box without moving into it is not a collision
+    #[test]
+    fn sweep_ignores_sliding_along_surface() {
+        let a_pos = Vec2::new(0.0, 2.0);
+        let a_size = Vec2::new(2.0, 2.0);
+        let displacement = Vec2::new(3.0, 0.0);
+        let b_pos = Vec2::new(0.0, 0.0);
+        let b_size = Vec2::new(10.0, 2.0);
+
+        let result = sweep_aabb(a_pos, a_size, displacement, b_pos, b_size);
+        assert!(result.is_none());
+    }
+
+    // overlapping boxes are handled by collide_aabb instead
+    #[test]
+    fn sweep_ignores_overlapping_boxes() {
+        let a_pos = Vec2::new(0.0, 0.5);
+        let a_size = Vec2::new(2.0, 2.0);
+        let displacement = Vec2::new(0.0, -5.0);
+        let b_pos = Vec2::new(0.0, 0.0);
+        let b_size = Vec2::new(10.0, 2.0);
+
+        let result = sweep_aabb(a_pos, a_size, displacement, b_pos, b_size);
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn contact_reports_depth_and_normal_from_top() {
+        let a_pos = Vec2::new(1.0, 2.5);
+        let a_size = Vec2::new(2.0, 2.0);
+        let b_pos = Vec2::new(0.0, 0.0);
+        let b_size = Vec2::new(10.0, 4.0);
+
+        let contact = contact_aabb(a_pos, a_size, b_pos, b_size).unwrap();
+
+        match contact.direction {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        assert_eq!(contact.normal, Vec2::new(0.0, 1.0));
+        assert!((contact.depth - 0.5).abs() < f32::EPSILON);
+        assert_eq!(contact.point, Vec2::new(1.0, 2.0));
+    }
+
+    #[test]
+    fn contact_reports_depth_and_normal_from_left() {
+        let a_pos = Vec2::new(-5.5, 0.0);
+        let a_size = Vec2::new(2.0, 2.0);
+        let b_pos = Vec2::new(0.0, 0.0);
+        let b_size = Vec2::new(10.0, 10.0);
+
+        let contact = contact_aabb(a_pos, a_size, b_pos, b_size).unwrap();
+
+        match contact.direction {
+            Collision::Left => assert!(true),
+            _ => assert!(false),
+        }
+        assert_eq!(contact.normal, Vec2::new(-1.0, 0.0));
+        assert!((contact.depth - 0.5).abs() < f32::EPSILON);
+        assert_eq!(contact.point, Vec2::new(-5.0, 0.0));
+    }
+
+    // pushing the box out along the normal by the depth leaves it touching the other box
+    #[test]
+    fn contact_resolves_off_center_boxes() {
+        let a_pos = Vec2::new(3.0, 6.0);
+        let a_size = Vec2::new(4.0, 8.0);
+        let b_pos = Vec2::new(0.0, 0.0);
+        let b_size = Vec2::new(10.0, 6.0);
+
+        let contact = contact_aabb(a_pos, a_size, b_pos, b_size).unwrap();
+        let resolved = a_pos + contact.normal * contact.depth;
+
+        assert!((resolved.y - a_size.y / 2.0 - (b_pos.y + b_size.y / 2.0)).abs() < f32::EPSILON);
+        assert!(contact_aabb(resolved, a_size, b_pos, b_size).is_none());
+    }
+
+    #[test]
+    fn ray_contact_reports_hit_point_and_remaining_length() {
+        let ray_pos = Vec2::new(0.0, 0.0);
+        let ray = Vec2::new(0.0, -5.0);
+        let hitbox_pos = Vec2::new(0.0, -2.0);
+        let hitbox_size = Vec2::new(4.0, 2.0);
+
+        let contact = contact_ray_box(ray_pos, ray, hitbox_pos, hitbox_size).unwrap();
+
+        match contact.direction {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        assert_eq!(contact.normal, Vec2::new(0.0, 1.0));
+        assert!((contact.point - Vec2::new(0.0, -1.0)).length() < 1e-5);
+        assert!((contact.depth - 4.0).abs() < 1e-5);
+    }
+
+    // a laser hitbox coming down onto a hurtbox
+    /*
+          *
+        __|__
+        | X |
+        |   |
+        |___|
+    */
+    #[test]
+    fn it_detects_ray_hitbox_hitting_rect_from_top() {
+        let hurt_shape = CollisionShape::Rect(Vec2::new(4.0, 4.0));
+        let hurt_pos = Vec2::new(0.0, 0.0);
+        let hit_shape = CollisionShape::Ray(Vec2::new(0.0, -5.0));
+        let hit_pos = Vec2::new(0.0, 4.0);
+
+        let contact = contact_shapes(&hurt_shape, hurt_pos, &hit_shape, hit_pos).unwrap();
+
+        // the hurtbox is under the laser, like hitting the bottom of a box
+        match contact.direction {
+            Collision::Bottom => assert!(true),
+            _ => assert!(false),
+        }
+        assert_eq!(contact.normal, Vec2::new(0.0, -1.0));
+        assert!((contact.point - Vec2::new(0.0, 2.0)).length() < 1e-5);
+        assert!((contact.depth - 3.0).abs() < 1e-5);
+    }
+
+    /*
+        ______
+        |    |
+    *---X--> |
+        |    |
+        |____|
+    */
+    #[test]
+    fn it_detects_ray_hitbox_hitting_rect_from_left() {
+        let hurt_shape = CollisionShape::Rect(Vec2::new(2.0, 4.0));
+        let hurt_pos = Vec2::new(3.0, 0.0);
+        let hit_shape = CollisionShape::Ray(Vec2::new(3.0, 0.0));
+        let hit_pos = Vec2::new(0.0, 0.0);
+
+        let contact = contact_shapes(&hurt_shape, hurt_pos, &hit_shape, hit_pos).unwrap();
+
+        match contact.direction {
+            Collision::Right => assert!(true),
+            _ => assert!(false),
+        }
+        assert_eq!(contact.normal, Vec2::new(1.0, 0.0));
+    }
+
+    #[test]
+    fn ray_hitbox_misses_rect() {
+        let hurt_shape = CollisionShape::Rect(Vec2::new(2.0, 2.0));
+        let hurt_pos = Vec2::new(10.0, 10.0);
+        let hit_shape = CollisionShape::Ray(Vec2::new(5.0, 0.0));
+        let hit_pos = Vec2::new(0.0, 0.0);
+
+        let result = contact_shapes(&hurt_shape, hurt_pos, &hit_shape, hit_pos);
+        assert!(result.is_none());
+    }
+
+    // checks a ray going right crossing a vertical ray
+    /*
+            ^
+            |
+      *-----X-->
+            |
+            *
+    */
+    #[test]
+    fn it_detects_ray_crossing_ray_from_left() {
+        let hurt_shape = CollisionShape::Ray(Vec2::new(4.0, 0.0));
+        let hurt_pos = Vec2::new(0.0, 0.0);
+        let hit_shape = CollisionShape::Ray(Vec2::new(0.0, 4.0));
+        let hit_pos = Vec2::new(3.0, -2.0);
+
+        let contact = contact_shapes(&hurt_shape, hurt_pos, &hit_shape, hit_pos).unwrap();
+
+        match contact.direction {
+            Collision::Left => assert!(true),
+            _ => assert!(false),
+        }
+        assert_eq!(contact.normal, Vec2::new(-1.0, 0.0));
+        assert!((contact.point - Vec2::new(3.0, 0.0)).length() < 1e-5);
+        assert!((contact.depth - 1.0).abs() < 1e-5);
+    }
+
+    /*
+          *
+          |
+      *---X--->
+          |
+          V
+    */
+    #[test]
+    fn it_detects_ray_crossing_ray_from_top() {
+        let hurt_shape = CollisionShape::Ray(Vec2::new(0.0, -4.0));
+        let hurt_pos = Vec2::new(0.0, 2.0);
+        let hit_shape = CollisionShape::Ray(Vec2::new(4.0, 0.0));
+        let hit_pos = Vec2::new(-2.0, 0.0);
+
+        let contact = contact_shapes(&hurt_shape, hurt_pos, &hit_shape, hit_pos).unwrap();
+
+        match contact.direction {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+    }
+
+    /*
+     *------->
+     *------->
+     */
+    #[test]
+    fn parallel_rays_do_not_collide() {
+        let hurt_shape = CollisionShape::Ray(Vec2::new(4.0, 0.0));
+        let hurt_pos = Vec2::new(0.0, 0.0);
+        let hit_shape = CollisionShape::Ray(Vec2::new(4.0, 0.0));
+        let hit_pos = Vec2::new(0.0, 1.0);
+
+        let result = contact_shapes(&hurt_shape, hurt_pos, &hit_shape, hit_pos);
+        assert!(result.is_none());
+    }
+
+    /*
+                ^
+      *--->     |
+                *
+    */
+    #[test]
+    fn ray_ends_before_ray() {
+        let hurt_shape = CollisionShape::Ray(Vec2::new(2.0, 0.0));
+        let hurt_pos = Vec2::new(0.0, 0.0);
+        let hit_shape = CollisionShape::Ray(Vec2::new(0.0, 4.0));
+        let hit_pos = Vec2::new(3.0, -2.0);
+
+        let result = contact_shapes(&hurt_shape, hurt_pos, &hit_shape, hit_pos);
+        assert!(result.is_none());
+    }
+
+    // every pairing gives the same contact no matter which shape is the hurtbox
+    #[test]
+    fn swapping_shapes_flips_the_contact() {
+        let shapes = [
+            (
+                CollisionShape::Rect(Vec2::new(2.0, 2.0)),
+                Vec2::new(0.0, 0.5),
+            ),
+            (
+                CollisionShape::Rect(Vec2::new(2.0, 4.0)),
+                Vec2::new(0.0, -2.0),
+            ),
+            (
+                CollisionShape::Ray(Vec2::new(0.0, -4.0)),
+                Vec2::new(0.2, 2.0),
+            ),
+            (
+                CollisionShape::Ray(Vec2::new(4.0, 0.0)),
+                Vec2::new(-2.0, -0.5),
+            ),
+        ];
+
+        for (a_shape, a_pos) in shapes.iter() {
+            for (b_shape, b_pos) in shapes.iter() {
+                if let (&CollisionShape::Ray(_), &CollisionShape::Ray(_)) = (a_shape, b_shape) {
+                    // ray pairs don't know which one did the hitting
+                    continue;
+                }
+                let a_hits_b = contact_shapes(a_shape, *a_pos, b_shape, *b_pos);
+                let b_hits_a = contact_shapes(b_shape, *b_pos, a_shape, *a_pos);
+                match (a_hits_b, b_hits_a) {
+                    (Some(a_hits_b), Some(b_hits_a)) => {
+                        assert_eq!(a_hits_b.normal, -b_hits_a.normal)
+                    }
+                    (None, None) => {}
+                    _ => assert!(false),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn circle_hits_box_from_top() {
+        let contact = contact_shapes(
+            &CollisionShape::Circle(1.0),
+            Vec2::new(0.0, 2.5),
+            &CollisionShape::Rect(Vec2::new(4.0, 4.0)),
+            Vec2::new(0.0, 0.0),
+        )
+        .unwrap();
+
+        match contact.direction {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        assert_eq!(contact.normal, Vec2::new(0.0, 1.0));
+        assert!((contact.depth - 0.5).abs() < 1e-5);
+        assert_eq!(contact.point, Vec2::new(0.0, 2.0));
+    }
+
+    // the normal points away from the corner so the circle slides off it instead of snagging
+    /*
+           _
+          (_)
+        ___/
+        |  |
+        |__|
+    */
+    #[test]
+    fn circle_hits_box_corner() {
+        let contact = contact_shapes(
+            &CollisionShape::Circle(1.0),
+            Vec2::new(2.5, 2.5),
+            &CollisionShape::Rect(Vec2::new(4.0, 4.0)),
+            Vec2::new(0.0, 0.0),
+        )
+        .unwrap();
+
+        assert!((contact.normal - Vec2::new(1.0, 1.0).normalize()).length() < 1e-5);
+        assert_eq!(contact.point, Vec2::new(2.0, 2.0));
+    }
+
+    #[test]
+    fn circle_misses_box_corner() {
+        let result = contact_shapes(
+            &CollisionShape::Circle(1.0),
+            Vec2::new(3.0, 3.0),
+            &CollisionShape::Rect(Vec2::new(4.0, 4.0)),
+            Vec2::new(0.0, 0.0),
+        );
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn capsule_hits_box_from_right() {
+        let contact = contact_shapes(
+            &CollisionShape::Capsule {
+                half_height: 2.0,
+                radius: 1.0,
+            },
+            Vec2::new(2.5, 0.0),
+            &CollisionShape::Rect(Vec2::new(4.0, 4.0)),
+            Vec2::new(0.0, 0.0),
+        )
+        .unwrap();
+
+        match contact.direction {
+            Collision::Right => assert!(true),
+            _ => assert!(false),
+        }
+        assert!((contact.depth - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn capsule_hits_box_from_top() {
+        let contact = contact_shapes(
+            &CollisionShape::Capsule {
+                half_height: 2.0,
+                radius: 1.0,
+            },
+            Vec2::new(1.0, 4.5),
+            &CollisionShape::Rect(Vec2::new(4.0, 4.0)),
+            Vec2::new(0.0, 0.0),
+        )
+        .unwrap();
+
+        match contact.direction {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        assert!((contact.depth - 0.5).abs() < 1e-5);
+        assert_eq!(contact.point, Vec2::new(1.0, 2.0));
+    }
+
+    #[test]
+    fn capsule_inside_box_is_pushed_out() {
+        let contact = contact_shapes(
+            &CollisionShape::Capsule {
+                half_height: 1.0,
+                radius: 1.0,
+            },
+            Vec2::new(0.0, 3.5),
+            &CollisionShape::Rect(Vec2::new(10.0, 10.0)),
+            Vec2::new(0.0, 0.0),
+        )
+        .unwrap();
+
+        match contact.direction {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        assert!((contact.depth - 3.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn circles_collide() {
+        let contact = contact_shapes(
+            &CollisionShape::Circle(1.0),
+            Vec2::new(0.0, 0.0),
+            &CollisionShape::Circle(1.0),
+            Vec2::new(1.5, 0.0),
+        )
+        .unwrap();
+
+        match contact.direction {
+            Collision::Left => assert!(true),
+            _ => assert!(false),
+        }
+        assert!((contact.depth - 0.5).abs() < 1e-5);
+        assert_eq!(contact.point, Vec2::new(0.5, 0.0));
+    }
+
+    #[test]
+    fn capsule_stands_on_capsule() {
+        let contact = contact_shapes(
+            &CollisionShape::Capsule {
+                half_height: 1.0,
+                radius: 1.0,
+            },
+            Vec2::new(0.5, 3.5),
+            &CollisionShape::Capsule {
+                half_height: 1.0,
+                radius: 1.0,
+            },
+            Vec2::new(0.0, 0.0),
+        );
+        assert!(result_is_top(contact));
+    }
+
+    fn result_is_top(contact: Option<Contact>) -> bool {
+        match contact {
+            Some(Contact {
+                direction: Collision::Top,
+                ..
+            }) => true,
+            _ => false,
+        }
+    }
+
+    /*
+          *
+          |
+          V
+         (_)
+    */
+    #[test]
+    fn ray_hits_circle_from_top() {
+        let contact = contact_shapes(
+            &CollisionShape::Ray(Vec2::new(0.0, -5.0)),
+            Vec2::new(0.0, 5.0),
+            &CollisionShape::Circle(1.0),
+            Vec2::new(0.0, 0.0),
+        )
+        .unwrap();
+
+        match contact.direction {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        assert!((contact.point - Vec2::new(0.0, 1.0)).length() < 1e-5);
+        assert!((contact.depth - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ray_hits_capsule_from_left() {
+        let contact = contact_shapes(
+            &CollisionShape::Ray(Vec2::new(10.0, 0.0)),
+            Vec2::new(-5.0, 0.5),
+            &CollisionShape::Capsule {
+                half_height: 2.0,
+                radius: 1.0,
+            },
+            Vec2::new(0.0, 0.0),
+        )
+        .unwrap();
+
+        match contact.direction {
+            Collision::Left => assert!(true),
+            _ => assert!(false),
+        }
+        assert!((contact.point - Vec2::new(-1.0, 0.5)).length() < 1e-5);
+    }
+
+    #[test]
+    fn ray_hits_capsule_end_from_top() {
+        let contact = contact_shapes(
+            &CollisionShape::Ray(Vec2::new(0.0, -10.0)),
+            Vec2::new(0.0, 10.0),
+            &CollisionShape::Capsule {
+                half_height: 2.0,
+                radius: 1.0,
+            },
+            Vec2::new(0.0, 0.0),
+        )
+        .unwrap();
+
+        match contact.direction {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        assert!((contact.point - Vec2::new(0.0, 3.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn ray_ends_before_circle() {
+        let result = contact_shapes(
+            &CollisionShape::Ray(Vec2::new(0.0, -3.0)),
+            Vec2::new(0.0, 5.0),
+            &CollisionShape::Circle(1.0),
+            Vec2::new(0.0, 0.0),
+        );
+        assert!(result.is_none());
+    }
+
+    /*
+           _
+          | |   /|
+          |_|  / |
+            /    |
+           /_____|
+    */
+    #[test]
+    fn rect_stands_on_slope_surface() {
+        let slope = CollisionShape::Slope {
+            size: Vec2::new(4.0, 2.0),
+            rises_right: true,
+        };
+        let contact = contact_shapes(
+            &CollisionShape::Rect(Vec2::new(1.0, 1.0)),
+            Vec2::new(0.0, 0.3),
+            &slope,
+            Vec2::new(0.0, 0.0),
+        )
+        .unwrap();
+
+        match contact.direction {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        // the surface is halfway up the slope in the middle
+        assert!((contact.point - Vec2::new(0.0, 0.0)).length() < 1e-5);
+        assert!((contact.depth - 0.2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn rect_above_slope_surface_does_not_collide() {
+        let slope = CollisionShape::Slope {
+            size: Vec2::new(4.0, 2.0),
+            rises_right: true,
+        };
+        // inside the slope's box but over the empty half of it
+        let result = contact_shapes(
+            &CollisionShape::Rect(Vec2::new(1.0, 1.0)),
+            Vec2::new(-1.0, 0.5),
+            &slope,
+            Vec2::new(0.0, 0.0),
+        );
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn rect_past_low_end_of_slope_does_not_collide() {
+        let slope = CollisionShape::Slope {
+            size: Vec2::new(4.0, 2.0),
+            rises_right: false,
+        };
+        let result = contact_shapes(
+            &CollisionShape::Rect(Vec2::new(1.0, 1.0)),
+            Vec2::new(2.2, -0.4),
+            &slope,
+            Vec2::new(0.0, 0.0),
+        );
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn rect_walks_into_high_end_of_slope() {
+        let slope = CollisionShape::Slope {
+            size: Vec2::new(4.0, 2.0),
+            rises_right: false,
+        };
+        let contact = contact_shapes(
+            &CollisionShape::Rect(Vec2::new(1.0, 1.0)),
+            Vec2::new(-2.4, -0.4),
+            &slope,
+            Vec2::new(0.0, 0.0),
+        )
+        .unwrap();
+
+        match contact.direction {
+            Collision::Left => assert!(true),
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn circle_stands_on_falling_slope() {
+        let contact = contact_shapes(
+            &CollisionShape::Circle(0.5),
+            Vec2::new(1.0, -0.2),
+            &CollisionShape::Slope {
+                size: Vec2::new(4.0, 2.0),
+                rises_right: false,
+            },
+            Vec2::new(0.0, 0.0),
+        )
+        .unwrap();
+
+        match contact.direction {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        assert!((contact.point - Vec2::new(1.0, -0.5)).length() < 1e-5);
+        assert!((contact.depth - 0.2).abs() < 1e-5);
+    }
+
+    /*
+          *
+          |
+          V  /|
+            / |
+           /__|
+    */
+    #[test]
+    fn ray_hits_slope_surface() {
+        let contact = contact_shapes(
+            &CollisionShape::Ray(Vec2::new(0.0, -5.0)),
+            Vec2::new(1.0, 3.0),
+            &CollisionShape::Slope {
+                size: Vec2::new(4.0, 2.0),
+                rises_right: true,
+            },
+            Vec2::new(0.0, 0.0),
+        )
+        .unwrap();
+
+        match contact.direction {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        assert!((contact.point - Vec2::new(1.0, 0.5)).length() < 1e-5);
+        // measured along the surface normal rather than the ray
+        assert!((contact.depth - 5.0f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn lands_on_one_way_when_falling_onto_top() {
+        // fell 5 units this step and ended up 2 units inside
+        assert!(lands_on_one_way(&Collision::Top, 2.0, -300.0, 1.0 / 60.0));
+    }
+
+    #[test]
+    fn lands_on_one_way_when_standing_on_top() {
+        assert!(lands_on_one_way(&Collision::Top, 0.0, 0.0, 1.0 / 60.0));
+    }
+
+    #[test]
+    fn passes_through_one_way_when_rising() {
+        assert!(!lands_on_one_way(&Collision::Top, 0.2, 100.0, 1.0 / 60.0));
+    }
+
+    #[test]
+    fn passes_through_one_way_when_falling_from_partially_inside() {
+        // the top of the jump ended partway into the platform, so it was never above it
+        assert!(!lands_on_one_way(&Collision::Top, 6.0, -120.0, 1.0 / 60.0));
+    }
+
+    #[test]
+    fn one_way_only_stops_from_top() {
+        assert!(!lands_on_one_way(&Collision::Left, 0.0, 0.0, 1.0 / 60.0));
+        assert!(!lands_on_one_way(&Collision::Right, 0.0, 0.0, 1.0 / 60.0));
+        assert!(!lands_on_one_way(&Collision::Bottom, 0.0, 0.0, 1.0 / 60.0));
+    }
+
+    /*
+           |
+        ___|
+        | ||
+        |_||
+         | |
+         v |
+        ===|
+    */
+    #[test]
+    fn sweep_move_keeps_sweeping_after_a_wall_stops_one_axis() {
+        let hurtbox = Hurtbox {
+            shape: CollisionShape::Rect(Vec2::new(2.0, 2.0)),
+            col_type: ColliderType::Player,
+        };
+        let wall = Hitbox {
+            shape: CollisionShape::Rect(Vec2::new(2.0, 100.0)),
+            col_type: ColliderType::Ground,
+        };
+        let floor = Hitbox {
+            shape: CollisionShape::Rect(Vec2::new(20.0, 1.0)),
+            col_type: ColliderType::Ground,
+        };
+        let hitboxes = [
+            (wall, Position(Vec2::new(2.0, 0.0)), Entity::new(0)),
+            (floor, Position(Vec2::new(0.0, 0.0)), Entity::new(1)),
+        ];
+        let matrix = CollisionMatrix {
+            interactions: vec![(ColliderType::Player, ColliderType::Ground)],
+        };
+
+        // touching the wall and pushing into it while falling fast enough to skip the floor
+        let (end, hits) = sweep_move(Vec2::new(0.0, 10.0), Vec2::new(1.0, -30.0), |from, d| {
+            hitboxes
+                .iter()
+                .filter_map(|(hitbox, position, entity)| {
+                    hurtbox.sweep_collision(&Position(from), d, hitbox, position, *entity, &matrix)
+                })
+                .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap())
+        });
+
+        assert_eq!(
+            hits.iter().map(|hit| hit.entity).collect::<Vec<_>>(),
+            vec![Entity::new(0), Entity::new(1)]
+        );
+        assert!((end - Vec2::new(0.0, 1.5)).length() < 1e-4);
+    }
+
+    /*
+           ____
+           |  |
+           |__|
+            ^
+            |
+        ==========
+    */
+    #[test]
+    fn sweep_passes_up_through_one_way() {
+        let hurtbox = Hurtbox {
+            shape: CollisionShape::Rect(Vec2::new(2.0, 2.0)),
+            col_type: ColliderType::Player,
+        };
+        let hitbox = Hitbox {
+            shape: CollisionShape::Rect(Vec2::new(10.0, 1.0)),
+            col_type: ColliderType::OneWayGround,
+        };
+        let matrix = CollisionMatrix {
+            interactions: vec![(ColliderType::Player, ColliderType::OneWayGround)],
+        };
+
+        let rising = hurtbox.sweep_collision(
+            &Position(Vec2::new(0.0, -3.0)),
+            Vec2::new(0.0, 6.0),
+            &hitbox,
+            &Position(Vec2::new(0.0, 0.0)),
+            Entity::new(0),
+            &matrix,
+        );
+        assert!(rising.is_none());
+
+        let (t, falling) = hurtbox
+            .sweep_collision(
+                &Position(Vec2::new(0.0, 3.0)),
+                Vec2::new(0.0, -6.0),
+                &hitbox,
+                &Position(Vec2::new(0.0, 0.0)),
+                Entity::new(0),
+                &matrix,
+            )
+            .unwrap();
+        match falling.direction {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        assert!((t - 0.25).abs() < 1e-5);
+    }
+
+    /*
+        ___
+       /   \
+       \___/
+            \
+             v
+              ____
+              |  |
+    */
+    #[test]
+    fn rounded_hurtboxes_are_swept_past_corners_their_bounds_would_catch() {
+        let hurtbox = Hurtbox {
+            shape: CollisionShape::Circle(2.0),
+            col_type: ColliderType::Player,
+        };
+        let hitbox = Hitbox {
+            shape: CollisionShape::Rect(Vec2::new(10.0, 10.0)),
+            col_type: ColliderType::Ground,
+        };
+        let matrix = CollisionMatrix {
+            interactions: vec![(ColliderType::Player, ColliderType::Ground)],
+        };
+
+        // the bounding box touches the corner two thirds of the way along, the circle only
+        // touches once its center is its radius away from the corner
+        let (t, swept) = hurtbox
+            .sweep_collision(
+                &Position(Vec2::new(-9.0, 9.0)),
+                Vec2::new(3.0, -3.0),
+                &hitbox,
+                &Position(Vec2::new(0.0, 0.0)),
+                Entity::new(0),
+                &matrix,
+            )
+            .unwrap();
+        assert!((t - (4.0 - 2.0f32.sqrt()) / 3.0).abs() < 1e-3);
+        assert_eq!(swept.depth, 0.0);
+
+        // and it's pushed away from the corner diagonally, not back along one axis
+        assert!(swept.normal.x < 0.0 && swept.normal.y > 0.0);
+        assert!((swept.normal.x + swept.normal.y).abs() < 1e-3);
+    }
+
+    /*
+          _
+         / \
+        |   |
+        |   |
+         \_/
+          |
+          v
+        ======
+    */
+    #[test]
+    fn rounded_hurtboxes_do_not_tunnel_through_thin_floors() {
+        let hurtbox = Hurtbox {
+            shape: CollisionShape::Capsule {
+                half_height: 4.0,
+                radius: 2.0,
+            },
+            col_type: ColliderType::Player,
+        };
+        let hitbox = Hitbox {
+            shape: CollisionShape::Rect(Vec2::new(20.0, 1.0)),
+            col_type: ColliderType::Ground,
+        };
+        let matrix = CollisionMatrix {
+            interactions: vec![(ColliderType::Player, ColliderType::Ground)],
+        };
+
+        let (t, swept) = hurtbox
+            .sweep_collision(
+                &Position(Vec2::new(0.0, 10.0)),
+                Vec2::new(0.0, -30.0),
+                &hitbox,
+                &Position(Vec2::new(0.0, 0.0)),
+                Entity::new(0),
+                &matrix,
+            )
+            .unwrap();
+        match swept.direction {
+            Collision::Top => assert!(true),
+            _ => assert!(false),
+        }
+        // the bottom of the capsule starts 3.5 above the floor's top
+        assert!((t - 3.5 / 30.0).abs() < 1e-3);
+
+        // sliding along the floor it's standing on isn't a hit
+        let sliding = hurtbox.sweep_collision(
+            &Position(Vec2::new(0.0, 6.5)),
+            Vec2::new(5.0, 0.0),
+            &hitbox,
+            &Position(Vec2::new(0.0, 0.0)),
+            Entity::new(0),
+            &matrix,
+        );
+        assert!(sliding.is_none());
+    }
+
+    // steps a jump until it starts falling, returning the highest point it reached
+    fn peak_jump_height(integrator: Integrator, jump_velocity: f32, gravity: f32, dt: f32) -> f32 {
+        let mut y = 0.0f32;
+        let mut peak = 0.0f32;
+        let mut v = Vec2::new(0.0, jump_velocity);
+        while v.y > 0.0 {
+            let (d, new_v) = integrator.step(v, Vec2::new(0.0, gravity), dt);
+            y += d.y;
+            v = new_v;
+            peak = peak.max(y);
+        }
+        peak
+    }
+
+    // the settings the game ships with, so the jump tests follow any tuning
+    fn settings() -> PhysicsSettings {
+        ron::de::from_str(include_str!("../assets/settings.physics.ron")).unwrap()
+    }
+
+    #[test]
+    fn verlet_jump_peak_matches_analytic_height() {
+        let s = settings();
+        let (jump_velocity, gravity) = (s.initial_jump_velocity, s.hold_gravity);
+        let analytic = jump_velocity * jump_velocity / (2.0 * -gravity);
+        for &tick_rate in [60.0f32, 120.0, 144.0].iter() {
+            let dt = 1.0 / tick_rate;
+            let peak = peak_jump_height(Integrator::VelocityVerlet, jump_velocity, gravity, dt);
+            // every step lands exactly on the parabola, so the only error is the peak falling
+            // between two steps
+            let tolerance = 0.5 * -gravity * dt * dt + 1e-3;
+            assert!((peak - analytic).abs() <= tolerance);
+        }
+    }
+
+    // the same jump made by the player, with everything else the physics stage does to it
+    #[test]
+    fn player_jump_peak_matches_analytic_height() {
+        let s = settings();
+        let (jump_velocity, gravity) = (s.initial_jump_velocity, s.hold_gravity);
+        let analytic = jump_velocity * jump_velocity / (2.0 * -gravity);
+        let dt = 1.0 / s.tick_rate;
+
+        let mut app = testing::app(s);
+        app.add_startup_system(crate::player::spawn_player);
+        testing::spawn_floor(&mut app, Vec2::new(0.0, -30.0), Vec2::new(240.0, 60.0));
+        app.update();
+        let mut stage = crate::physics_stage();
+        let player = testing::player(&mut app);
+        let height = |app: &App| app.world.get::<Position>(player).unwrap().0.y;
+
+        for _ in 0..10 {
+            testing::step(&mut app, &mut stage, Actions::default());
+        }
+        let start = height(&app);
+        testing::step(
+            &mut app,
+            &mut stage,
+            testing::actions(&[Action::Jump], &[Action::Jump]),
+        );
+        assert!(height(&app) > start);
+        let mut peak = start;
+        while height(&app) >= peak {
+            peak = height(&app);
+            testing::step(&mut app, &mut stage, testing::actions(&[Action::Jump], &[]));
+        }
+
+        let tolerance = 0.5 * -gravity * dt * dt + 1e-2;
+        assert!((peak - start - analytic).abs() <= tolerance);
+    }
+
+    #[test]
+    fn symplectic_euler_jump_peak_is_within_half_a_step() {
+        let s = settings();
+        let (jump_velocity, gravity) = (s.initial_jump_velocity, s.hold_gravity);
+        let analytic = jump_velocity * jump_velocity / (2.0 * -gravity);
+        for &tick_rate in [60.0f32, 120.0, 144.0].iter() {
+            let dt = 1.0 / tick_rate;
+            let peak = peak_jump_height(Integrator::SymplecticEuler, jump_velocity, gravity, dt);
+            // euler loses half a step of the jump velocity's worth of height
+            let tolerance = 0.5 * jump_velocity * dt + 0.5 * -gravity * dt * dt + 1e-3;
+            assert!(peak <= analytic);
+            assert!((peak - analytic).abs() <= tolerance);
+        }
+    }
+
+    #[test]
+    fn cap_speed_limits_running_both_ways() {
+        assert_eq!(cap_speed(Vec2::new(500.0, 0.0), 300.0, 1000.0).x, 300.0);
+        assert_eq!(cap_speed(Vec2::new(-500.0, 0.0), 300.0, 1000.0).x, -300.0);
+        assert_eq!(cap_speed(Vec2::new(200.0, 0.0), 300.0, 1000.0).x, 200.0);
+    }
+
+    #[test]
+    fn cap_speed_only_limits_falling() {
+        assert_eq!(cap_speed(Vec2::new(0.0, -1500.0), 300.0, 1000.0).y, -1000.0);
+        // jumping is left to the jump velocity
+        assert_eq!(cap_speed(Vec2::new(0.0, 1500.0), 300.0, 1000.0).y, 1500.0);
+    }
+
+    #[test]
+    fn capped_step_keeps_speed_it_did_not_accelerate_to() {
+        let dt = 1.0 / 60.0;
+        let (_, v) = capped_step(
+            Integrator::SymplecticEuler,
+            Vec2::new(900.0, 0.0),
+            Vec2::ZERO,
+            dt,
+            300.0,
+            1000.0,
+        );
+        assert_eq!(v.x, 900.0);
+        let (_, v) = capped_step(
+            Integrator::SymplecticEuler,
+            Vec2::new(900.0, 0.0),
+            Vec2::new(600.0, 0.0),
+            dt,
+            300.0,
+            1000.0,
+        );
+        assert_eq!(v.x, 900.0);
+    }
+
+    #[test]
+    fn falling_reaches_terminal_velocity() {
+        let dt = 1.0 / 60.0;
+        for &integrator in [Integrator::SymplecticEuler, Integrator::VelocityVerlet].iter() {
+            let mut v = Vec2::ZERO;
+            let mut d = Vec2::ZERO;
+            for _ in 0..120 {
+                let (new_d, new_v) =
+                    capped_step(integrator, v, Vec2::new(0.0, -7000.0), dt, 300.0, 1000.0);
+                d = new_d;
+                v = new_v;
+            }
+            assert_eq!(v.y, -1000.0);
+            assert!((d.y + 1000.0 * dt).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn spatial_hash_only_returns_nearby_entities() {
+        let mut spatial_hash = SpatialHash::new(10.0);
+        let near = Entity::new(0);
+        let far = Entity::new(1);
+        spatial_hash.insert(near, Vec2::new(0.0, 0.0), Vec2::new(5.0, 5.0));
+        spatial_hash.insert(far, Vec2::new(100.0, 100.0), Vec2::new(105.0, 105.0));
+
+        let result = spatial_hash.query(Vec2::new(-2.0, -2.0), Vec2::new(2.0, 2.0));
+        assert_eq!(result, vec![near]);
+    }
+
+    #[test]
+    fn spatial_hash_does_not_duplicate_entities_spanning_cells() {
+        let mut spatial_hash = SpatialHash::new(10.0);
+        let wide = Entity::new(0);
+        spatial_hash.insert(wide, Vec2::new(-25.0, -5.0), Vec2::new(25.0, 5.0));
+
+        let result = spatial_hash.query(Vec2::new(-30.0, -30.0), Vec2::new(30.0, 30.0));
+        assert_eq!(result, vec![wide]);
+    }
+
+    #[test]
+    fn spatial_hash_moves_reinserted_entities() {
+        let mut spatial_hash = SpatialHash::new(10.0);
+        let entity = Entity::new(0);
+        spatial_hash.insert(entity, Vec2::new(0.0, 0.0), Vec2::new(5.0, 5.0));
+        spatial_hash.insert(entity, Vec2::new(100.0, 0.0), Vec2::new(105.0, 5.0));
+
+        assert!(spatial_hash
+            .query(Vec2::new(0.0, 0.0), Vec2::new(5.0, 5.0))
+            .is_empty());
+        assert_eq!(
+            spatial_hash.query(Vec2::new(100.0, 0.0), Vec2::new(105.0, 5.0)),
+            vec![entity]
+        );
+    }
+
+    // lays out a large level of ground tiles and checks a hurtbox only gets narrow phased
+    // against the tiles around it
+    #[test]
+    fn spatial_hash_scales_to_thousands_of_hitboxes() {
+        let mut world = World::default();
+        world.insert_resource(SpatialHash::default());
+
+        let tile_size = Vec2::new(30.0, 30.0);
+        for x in 0..100 {
+            for y in 0..100 {
+                world
+                    .spawn()
+                    .insert(Hitbox {
+                        shape: CollisionShape::Rect(tile_size),
+                        col_type: ColliderType::Ground,
+                    })
+                    .insert(Position(Vec2::new(x as f32, y as f32) * tile_size));
+            }
+        }
+        let hurtbox = Hurtbox {
+            shape: CollisionShape::Rect(Vec2::new(30.0, 30.0)),
+            col_type: ColliderType::Player,
+        };
+        let hurt_position = Position(Vec2::new(1500.0, 1510.0));
+        let matrix = CollisionMatrix {
+            interactions: vec![(ColliderType::Player, ColliderType::Ground)],
+        };
+
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(update_spatial_hash);
+        stage.run(&mut world);
+
+        let (min, max) = hurtbox.shape.bounds(hurt_position.0);
+        let candidates = world.get_resource::<SpatialHash>().unwrap().query(min, max);
+        assert!(candidates.len() < 20);
+
+        let hitbox_entities: Vec<Entity> = world
+            .query_filtered::<Entity, With<Hitbox>>()
+            .iter(&world)
+            .collect();
+        let collide = |hit_entity: Entity| {
+            let hitbox = world.get::<Hitbox>(hit_entity).unwrap();
+            let hitbox_position = world.get::<Position>(hit_entity).unwrap();
+            hurtbox
+                .check_collision(&hurt_position, hitbox, hitbox_position, hit_entity, &matrix)
+                .map(|collision| collision.entity)
+        };
+        let mut narrow_phased: Vec<Entity> =
+            candidates.iter().filter_map(|&e| collide(e)).collect();
+        let mut brute_forced: Vec<Entity> =
+            hitbox_entities.iter().filter_map(|&e| collide(e)).collect();
+        narrow_phased.sort_unstable();
+        brute_forced.sort_unstable();
+        assert!(!brute_forced.is_empty());
+        assert_eq!(narrow_phased, brute_forced);
+
+        // static hitboxes are not reinserted on later steps, so one taken out by hand stays out
+        // while one that moved is put back where it now is
+        let (still, moved) = (candidates[0], candidates[1]);
+        world
+            .get_resource_mut::<SpatialHash>()
+            .unwrap()
+            .remove(still);
+        world
+            .get_resource_mut::<SpatialHash>()
+            .unwrap()
+            .remove(moved);
+        world.get_mut::<Position>(moved).unwrap().0 = Vec2::new(-500.0, -500.0);
+        stage.run(&mut world);
+
+        let spatial_hash = world.get_resource::<SpatialHash>().unwrap();
+        let candidates = spatial_hash.query(min, max);
+        assert!(!candidates.contains(&still));
+        assert!(!candidates.contains(&moved));
+        let (min, max) = CollisionShape::Rect(tile_size).bounds(Vec2::new(-500.0, -500.0));
+        assert_eq!(spatial_hash.query(min, max), vec![moved]);
+    }
 }
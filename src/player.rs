@@ -1,9 +1,11 @@
-use crate::ground::Ground;
+use crate::ground::{Ground, MovingPlatform};
+use crate::input::{Action, Actions};
 use crate::physics::{
-    Acceleration, ColliderType, Collision, CollisionShape, CollisionType, Collisions, Hurtbox,
-    PhysicsSettings, PhysicsSettingsHandle, Position, Velocity,
+    lands_on_one_way, teleport, Acceleration, ColliderType, Collision, CollisionData,
+    CollisionShape, Collisions, Displacement, DropThrough, Hurtbox, PhysicsSettings,
+    PhysicsSettingsHandle, PhysicsTimestep, Position, PreviousPosition, Velocity,
 };
-use crate::player_fsm::{PlayerFSM, PlayerMemory, PlayerState};
+use crate::player_fsm::{PlayerFSM, PlayerInput, PlayerMemory, PlayerState};
 use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
 use std::borrow::BorrowMut;
@@ -14,7 +16,70 @@ pub struct Player;
 #[derive(Component)]
 pub struct PlayerRay;
 
-pub fn spawn_player(mut commands: Commands, mut material_assets: ResMut<Assets<ColorMaterial>>) {
+// rays reaching just past the player's sides to find walls, pointing -1 for left and 1 for right
+#[derive(Component)]
+pub struct PlayerSideRay(pub f32);
+
+// how far past the player's sides a wall can be and still be slid down or jumped off
+const WALL_REACH: f32 = 2.0;
+
+// sides of the player that were touching ground during the last physics step
+#[derive(Component, Default, Clone, Copy)]
+pub struct Contacts {
+    pub ground: bool,
+    pub ceiling: bool,
+    pub left_wall: bool,
+    pub right_wall: bool,
+    // what the player is standing on, so moving platforms can carry it
+    pub ground_entity: Option<Entity>,
+    // touching anything that kills the player
+    pub hazard: bool,
+}
+
+// where the player comes back after dying
+pub struct SpawnPoint(pub Vec2);
+
+impl Default for SpawnPoint {
+    fn default() -> Self {
+        SpawnPoint(Vec2::new(0.0, 15.0))
+    }
+}
+
+// fixed ticks left before a dead player respawns, None while alive
+#[derive(Component, Default)]
+pub struct Respawn(pub Option<u32>);
+
+// fixed ticks left in which a jump is still allowed after leaving the ground, in which a jump
+// pressed too early is still waiting to happen, and in which a wall jump ignores sideways input.
+// the coyote and buffer windows count the tick they open in, so a setting of 0 still allows that
+// tick. also counts the air jumps left before landing
+#[derive(Component, Default, Clone, Copy)]
+pub struct JumpTimers {
+    pub coyote: u32,
+    pub buffer: u32,
+    pub wall_lock: u32,
+    pub air_jumps: u32,
+}
+
+// fixed ticks left in the current dash, before another dash is allowed, and in which a dash
+// pressed too early is still waiting to happen. like the jump buffer, the dash buffer counts the
+// tick it opens in. only one dash is allowed between landings
+#[derive(Component, Default, Clone, Copy)]
+pub struct DashTimers {
+    pub active: u32,
+    pub cooldown: u32,
+    pub buffer: u32,
+    pub spent: bool,
+}
+
+// how far above the ground the player's feet can be and still count as standing on it
+const GROUNDED_DISTANCE: f32 = 1.0;
+
+pub fn spawn_player(
+    mut commands: Commands,
+    mut material_assets: ResMut<Assets<ColorMaterial>>,
+    spawn_point: Res<SpawnPoint>,
+) {
     let material = material_assets.add(Color::rgb(0.7, 0.7, 0.7).into());
 
     commands
@@ -22,18 +87,26 @@ pub fn spawn_player(mut commands: Commands, mut material_assets: ResMut<Assets<C
         .insert_bundle(SpriteBundle {
             material: material.clone(),
             sprite: Sprite::new(Vec2::new(30.0, 30.0)),
-            transform: Transform::from_translation(Vec3::new(0.0, 15.0, 0.0)),
+            transform: Transform::from_translation(spawn_point.0.extend(0.0)),
             ..Default::default()
         })
         .insert(Player)
         .insert(Velocity(Vec2::new(0.0, 0.0)))
-        .insert(Position(Vec2::new(0.0, 15.0)))
+        .insert(Displacement::default())
+        .insert(Position(spawn_point.0))
+        .insert(PreviousPosition(spawn_point.0))
         .insert(Acceleration(Vec2::new(0.0, 0.0)))
         .insert(Hurtbox {
             shape: CollisionShape::Rect(Vec2::new(30.0, 30.0)),
             col_type: ColliderType::Player,
         })
-        .insert(Collisions(Vec::new()));
+        .insert(Collisions(Vec::new()))
+        .insert(Contacts::default())
+        .insert(DropThrough::default())
+        .insert(JumpTimers::default())
+        .insert(DashTimers::default())
+        .insert(Respawn::default())
+        .insert(PlayerFSM::new());
 
     commands
         .spawn()
@@ -42,58 +115,435 @@ pub fn spawn_player(mut commands: Commands, mut material_assets: ResMut<Assets<C
             shape: CollisionShape::Ray(Vec2::new(0.0, -30.1)),
             col_type: ColliderType::PlayerRay,
         })
-        .insert(Position(Vec2::new(0.0, 15.0)))
-        .insert(Collisions(Vec::new()))
-        .insert(PlayerFSM::new());
+        .insert(Position(spawn_point.0))
+        .insert(Collisions(Vec::new()));
+
+    for &side in [-1.0f32, 1.0].iter() {
+        commands
+            .spawn()
+            .insert(PlayerSideRay(side))
+            .insert(Hurtbox {
+                shape: CollisionShape::Ray(Vec2::new(side * (15.0 + WALL_REACH), 0.0)),
+                col_type: ColliderType::PlayerRay,
+            })
+            .insert(Position(spawn_point.0))
+            .insert(Collisions(Vec::new()));
+    }
 }
 
-pub fn player_input<'r, 'w, 's, 't0, 't1, 't2, 'r0, 'r1>(
-    keyboard_input: Res<'r, Input<KeyCode>>,
-    mut query: Query<
+// keeps the player's hurtbox and ground ray in sync with the shape in the physics settings
+pub fn update_player_hurtbox(
+    mut events: EventReader<AssetEvent<PhysicsSettings>>,
+    physics_settings: Res<Assets<PhysicsSettings>>,
+    mut player_q: Query<&mut Hurtbox, (With<Player>, Without<PlayerRay>)>,
+    mut ray_q: Query<&mut Hurtbox, (With<PlayerRay>, Without<Player>)>,
+    mut side_ray_q: Query<(&mut Hurtbox, &PlayerSideRay), (Without<Player>, Without<PlayerRay>)>,
+) {
+    for event in events.iter() {
+        let s = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                match physics_settings.get(handle) {
+                    Some(s) => s,
+                    None => continue,
+                }
+            }
+            AssetEvent::Removed { .. } => continue,
+        };
+
+        for mut hurtbox in player_q.iter_mut() {
+            hurtbox.shape = s.player_hurtbox;
+        }
+
+        // reach just past the bottom of the hurtbox
+        let (min, _) = s.player_hurtbox.bounds(Vec2::ZERO);
+        for mut ray in ray_q.iter_mut() {
+            ray.shape = CollisionShape::Ray(Vec2::new(0.0, min.y * 2.0 - 0.1));
+        }
+
+        let (_, max) = s.player_hurtbox.bounds(Vec2::ZERO);
+        for (mut ray, side) in side_ray_q.iter_mut() {
+            ray.shape = CollisionShape::Ray(Vec2::new(side.0 * (max.x + WALL_REACH), 0.0));
+        }
+    }
+}
+
+pub fn player_input(
+    actions: Res<Actions>,
+    mut query: Query<(&mut JumpTimers, &mut DashTimers), With<Player>>,
+    physics_settings: Res<Assets<PhysicsSettings>>,
+    physics_settings_handle: Res<PhysicsSettingsHandle>,
+) {
+    let s = match physics_settings.get(&physics_settings_handle.0) {
+        Some(s) => s,
+        None => return,
+    };
+
+    let (mut jump_timers, mut dash_timers) = query.single_mut();
+
+    // down + jump drops through one-way platforms instead. the jump itself happens in the physics
+    // set once the player is allowed to, and gravity is left to the player FSM
+    if actions.just_pressed(Action::Jump) && !actions.pressed(Action::Down) {
+        jump_timers.buffer = s.jump_buffer_ticks + 1;
+    }
+    if actions.just_pressed(Action::Dash) {
+        dash_timers.buffer = s.dash_buffer_ticks + 1;
+    }
+}
+
+pub fn read_player_input(actions: &Actions) -> PlayerInput {
+    PlayerInput {
+        left: actions.pressed(Action::MoveLeft),
+        right: actions.pressed(Action::MoveRight),
+        jump_held: actions.pressed(Action::Jump),
+    }
+}
+
+// everything the player FSM can look at, borrowed together so new states don't need new system
+// arguments
+#[derive(SystemParam)]
+pub struct PlayerParams<'w, 's> {
+    actions: Res<'w, Actions>,
+    players: Query<
         'w,
         's,
-        (&'t0 mut Velocity, &'t1 mut Acceleration, &'t2 PlayerFSM<'t1>),
+        (
+            &'static mut Velocity,
+            &'static mut Acceleration,
+            &'static Contacts,
+            &'static mut JumpTimers,
+            &'static mut DashTimers,
+        ),
         With<Player>,
     >,
-    physics_settings: Res<'r0, Assets<PhysicsSettings>>,
-    physics_settings_handle: Res<'r1, PhysicsSettingsHandle>,
+    side_rays: Query<'w, 's, (&'static PlayerSideRay, &'static Collisions)>,
+    physics_settings: Res<'w, Assets<PhysicsSettings>>,
+    physics_settings_handle: Res<'w, PhysicsSettingsHandle>,
+}
+
+impl<'w, 's> PlayerParams<'w, 's> {
+    // None until the physics settings have loaded
+    pub fn memory(self: &Self, player: Entity) -> Option<PlayerMemory> {
+        let s = self.settings()?;
+        let (v, a, contacts, timers, dash) = self.players.get(player).ok()?;
+        Some(PlayerMemory {
+            v: v.0,
+            a: a.0,
+            settings: s.into(),
+            contacts: *contacts,
+            wall: self.wall(contacts),
+            timers: *timers,
+            dash: *dash,
+            input: read_player_input(&self.actions),
+        })
+    }
+
+    pub fn settings(self: &Self) -> Option<&PhysicsSettings> {
+        self.physics_settings.get(&self.physics_settings_handle.0)
+    }
+
+    pub fn write_back(self: &mut Self, player: Entity, memory: &PlayerMemory) {
+        if let Ok((mut v, mut a, _, mut timers, mut dash)) = self.players.get_mut(player) {
+            v.0 = memory.v;
+            a.0 = memory.a;
+            *timers = memory.timers;
+            *dash = memory.dash;
+        }
+    }
+
+    // side of the player with a wall against it or just within reach of the side rays
+    fn wall(self: &Self, contacts: &Contacts) -> Option<f32> {
+        if contacts.left_wall {
+            return Some(-1.0);
+        }
+        if contacts.right_wall {
+            return Some(1.0);
+        }
+        self.side_rays
+            .iter()
+            .find(|(_, cs)| {
+                // ramps are walked up, not slid down
+                cs.0.iter().any(|c| {
+                    c.collision_type.hit == ColliderType::Ground
+                        && !matches!(c.collision_type.hit_shape, CollisionShape::Slope { .. })
+                })
+            })
+            .map(|(side, _)| side.0)
+    }
+}
+
+// the parts of the player that a respawn or a replay restart put back to how they were at spawn
+#[derive(SystemParam)]
+pub struct PlayerReset<'w, 's> {
+    players: Query<
+        'w,
+        's,
+        (
+            &'static mut Position,
+            &'static mut PreviousPosition,
+            &'static mut Velocity,
+            &'static mut Acceleration,
+            &'static mut Contacts,
+            &'static mut JumpTimers,
+            &'static mut DashTimers,
+            &'static mut DropThrough,
+        ),
+        With<Player>,
+    >,
+}
+
+impl<'w, 's> PlayerReset<'w, 's> {
+    // puts the player at `to`, standing still and touching nothing, with no timers running and
+    // nothing buffered. the FSM is left to the caller
+    pub fn reset(self: &mut Self, player: Entity, to: Vec2) {
+        if let Ok((
+            mut p,
+            mut previous,
+            mut v,
+            mut a,
+            mut contacts,
+            mut jump_timers,
+            mut dash_timers,
+            mut drop_through,
+        )) = self.players.get_mut(player)
+        {
+            teleport(&mut p, &mut previous, to);
+            v.0 = Vec2::ZERO;
+            a.0 = Vec2::ZERO;
+            *contacts = Contacts::default();
+            *jump_timers = JumpTimers::default();
+            *dash_timers = DashTimers::default();
+            drop_through.0 = 0;
+        }
+    }
+}
+
+// shrinks a dead player away, then puts it back at the spawn point as if it had just landed
+pub fn respawn_player(
+    mut player_q: Query<(Entity, &mut PlayerFSM, &mut Respawn, &mut Transform), With<Player>>,
+    mut reset: PlayerReset,
+    spawn_point: Res<SpawnPoint>,
+    physics_settings: Res<Assets<PhysicsSettings>>,
+    physics_settings_handle: Res<PhysicsSettingsHandle>,
 ) {
-    let s: &PhysicsSettings = physics_settings
-        .get(&physics_settings_handle.0)
-        .expect("no physics settings found");
+    let s = match physics_settings.get(&physics_settings_handle.0) {
+        Some(s) => s,
+        None => return,
+    };
+
+    let delay = s.respawn_delay_ticks;
+    for (entity, mut fsm, mut respawn, mut t) in player_q.iter_mut() {
+        if !fsm.is_dead() {
+            continue;
+        }
 
-    let (mut v, mut a, mut fsm) = query.single_mut();
+        match respawn.0 {
+            None => respawn.0 = Some(delay),
+            Some(0) => {
+                respawn.0 = None;
+                t.scale = Vec3::ONE;
+                reset.reset(entity, spawn_point.0);
+                fsm.0
+                    .change_active_state(
+                        Some(PlayerState::OnGround),
+                        &mut PlayerMemory::at_rest(s),
+                        true,
+                    )
+                    .unwrap();
+            }
+            Some(ticks) => {
+                respawn.0 = Some(ticks - 1);
+                t.scale = Vec3::splat(ticks as f32 / delay.max(1) as f32);
+            }
+        }
+    }
+}
+
+// runs the player FSM once per physics step, after everything that decides whether the player is
+// standing on the ground
+pub fn update_player_fsm(
+    mut fsm_q: Query<(Entity, &mut PlayerFSM), With<Player>>,
+    mut player: PlayerParams,
+) {
+    for (entity, mut fsm) in fsm_q.iter_mut() {
+        let mut memory = match player.memory(entity) {
+            Some(memory) => memory,
+            None => continue,
+        };
+        if fsm.0.active_state().is_none() {
+            fsm.0
+                .change_active_state(Some(PlayerState::InAirReleasedB), &mut memory, true)
+                .unwrap();
+        }
+        fsm.0.update(&mut memory);
+        fsm.0.process(&mut memory);
+        player.write_back(entity, &memory);
+    }
+}
+
+// the FSM only sees the jump at the end of the tick, so gravity is set here too or the first tick
+// of the jump would have none
+pub fn player_jump(
+    mut query: Query<
+        (
+            &mut Velocity,
+            &mut Acceleration,
+            &mut JumpTimers,
+            &PlayerFSM,
+        ),
+        With<Player>,
+    >,
+    physics_settings: Res<Assets<PhysicsSettings>>,
+    physics_settings_handle: Res<PhysicsSettingsHandle>,
+) {
+    let s = match physics_settings.get(&physics_settings_handle.0) {
+        Some(s) => s,
+        None => return,
+    };
 
-    if keyboard_input.just_pressed(KeyCode::Space) && a.0.y == 0.0 {
+    for (mut v, mut a, mut jump_timers, fsm) in query.iter_mut() {
+        // a buffered jump waits out a dash
+        if jump_timers.buffer == 0 || jump_timers.coyote == 0 || fsm.is_dead() || fsm.is_dashing() {
+            continue;
+        }
         v.0.y = s.initial_jump_velocity;
-        let mut player_memory = PlayerMemory { a };
-        fsm.0
-            .change_active_state(Some(PlayerState::InAirPressedB), &mut player_memory, true)
-            .unwrap();
-        fsm.0.update(&mut player_memory);
         a.0.y = s.hold_gravity;
+        jump_timers.buffer = 0;
+        jump_timers.coyote = 0;
+    }
+}
+
+// refreshes coyote time and air jumps while the ground ray finds ground under the player's feet,
+// and counts down coyote time otherwise
+pub fn update_jump_timers(
+    mut player_q: Query<
+        (
+            &mut JumpTimers,
+            &Position,
+            &Velocity,
+            &Hurtbox,
+            &DropThrough,
+        ),
+        With<Player>,
+    >,
+    ray_q: Query<&Collisions, With<PlayerRay>>,
+    grounds_q: Query<Entity, With<Ground>>,
+    physics_settings: Res<Assets<PhysicsSettings>>,
+    physics_settings_handle: Res<PhysicsSettingsHandle>,
+) {
+    let s = match physics_settings.get(&physics_settings_handle.0) {
+        Some(s) => s,
+        None => return,
+    };
+
+    for (mut jump_timers, p, v, hurtbox, drop_through) in player_q.iter_mut() {
+        let (min, _) = hurtbox.shape.bounds(p.0);
+        // the platform being dropped through doesn't count, or it would hand out a free jump
+        let grounded = v.0.y <= 0.0
+            && ground_below(&ray_q, &grounds_q, drop_through.0 > 0)
+                .map_or(false, |(y, _)| min.y - y <= GROUNDED_DISTANCE);
+
+        jump_timers.coyote = if grounded {
+            jump_timers.air_jumps = s.air_jumps;
+            s.coyote_ticks + 1
+        } else {
+            jump_timers.coyote.saturating_sub(1)
+        };
+    }
+}
+
+// runs after the player FSM so a press is still buffered when the FSM looks for air jumps, wall
+// jumps and dashes in the tick it was made
+pub fn count_down_buffers(mut player_q: Query<(&mut JumpTimers, &mut DashTimers), With<Player>>) {
+    for (mut jump_timers, mut dash_timers) in player_q.iter_mut() {
+        jump_timers.buffer = jump_timers.buffer.saturating_sub(1);
+        dash_timers.buffer = dash_timers.buffer.saturating_sub(1);
+    }
+}
+
+// counts down the dash cooldown. the dash itself is counted down by the player FSM
+pub fn update_dash_timers(mut player_q: Query<&mut DashTimers, With<Player>>) {
+    for mut dash_timers in player_q.iter_mut() {
+        dash_timers.cooldown = dash_timers.cooldown.saturating_sub(1);
+    }
+}
+
+// highest piece of ground the player ray hits, as its height and entity
+fn ground_below(
+    ray_q: &Query<&Collisions, With<PlayerRay>>,
+    grounds_q: &Query<Entity, With<Ground>>,
+    dropping_through: bool,
+) -> Option<(f32, Entity)> {
+    highest_ground(
+        ray_q.iter().flat_map(|cs| cs.0.iter()),
+        |entity| grounds_q.get(entity).is_ok(),
+        dropping_through,
+    )
+}
+
+// skips one-way platforms while dropping through them
+fn highest_ground<'a>(
+    hits: impl Iterator<Item = &'a CollisionData>,
+    is_ground: impl Fn(Entity) -> bool,
+    dropping_through: bool,
+) -> Option<(f32, Entity)> {
+    let mut ground: Option<(f32, Entity)> = None;
+    for collision_data in hits {
+        if !matches!(collision_data.direction, Collision::Top)
+            || !is_ground(collision_data.entity)
+            || (dropping_through && collision_data.collision_type.hit.is_one_way())
+        {
+            continue;
+        }
+        let y = collision_data.point.y;
+        if ground.map_or(true, |(ground_y, _)| y > ground_y) {
+            ground = Some((y, collision_data.entity));
+        }
     }
+    ground
+}
+
+pub fn player_drop_through(
+    actions: Res<Actions>,
+    mut query: Query<(&mut DropThrough, &Contacts), With<Player>>,
+    physics_settings: Res<Assets<PhysicsSettings>>,
+    physics_settings_handle: Res<PhysicsSettingsHandle>,
+) {
+    let s = match physics_settings.get(&physics_settings_handle.0) {
+        Some(s) => s,
+        None => return,
+    };
 
-    if keyboard_input.just_released(KeyCode::Space) {
-        a.0.y = s.normal_gravity;
+    let (mut drop_through, contacts) = query.single_mut();
+    if actions.pressed(Action::Down) && actions.just_pressed(Action::Jump) && contacts.ground {
+        drop_through.0 = s.drop_through_steps;
     }
 }
 
 pub fn player_horizontal_accel<'a>(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<(&mut Velocity, &mut Acceleration), With<Player>>,
+    actions: Res<Actions>,
+    mut query: Query<(&mut Velocity, &mut Acceleration, &Contacts, &PlayerFSM), With<Player>>,
     physics_settings: Res<Assets<PhysicsSettings>>,
     physics_settings_handle: Res<PhysicsSettingsHandle>,
 ) {
-    let s = physics_settings
-        .get(&physics_settings_handle.0)
-        .expect("no physics settings found");
-
-    let (mut v, mut a) = query.single_mut();
-    if keyboard_input.pressed(KeyCode::A) {
-        a.0.x = -s.horizontal_a;
-    } else if keyboard_input.pressed(KeyCode::D) {
-        a.0.x = s.horizontal_a;
+    let s = match physics_settings.get(&physics_settings_handle.0) {
+        Some(s) => s,
+        None => return,
+    };
+
+    let (mut v, mut a, contacts, fsm) = query.single_mut();
+    // the FSM has control of the player while it's dead, dashing or kicking off a wall
+    if fsm.is_dead() || fsm.is_dashing() || fsm.0.active_state() == Some(&PlayerState::WallJump) {
+        return;
+    }
+    let horizontal_a = if contacts.ground {
+        s.horizontal_a
+    } else {
+        s.air_horizontal_a
+    };
+    // half pushed sticks accelerate at half the rate
+    let move_x = actions.move_x();
+    if move_x != 0.0 {
+        a.0.x = horizontal_a * move_x;
     } else if v.0.x > s.stopping_horizontal_speed {
         a.0.x = -s.friction;
     } else if v.0.x < -s.stopping_horizontal_speed {
@@ -104,39 +554,207 @@ pub fn player_horizontal_accel<'a>(
     }
 }
 
+// contacts are rebuilt every sub-step, but a hazard touched in any of them has to last until the
+// FSM sees it at the end of the tick
+pub fn forget_hazards(mut player_q: Query<&mut Contacts, With<Player>>) {
+    for mut contacts in player_q.iter_mut() {
+        contacts.hazard = false;
+    }
+}
+
 pub fn handle_player_collides_ground(
     mut player_q: Query<
         (
             &mut Position,
             &mut Velocity,
             &mut Acceleration,
+            &mut Contacts,
+            &DropThrough,
+            &DashTimers,
             &Collisions,
-            &Sprite,
         ),
-        (With<Player>, Changed<Collisions>),
+        With<Player>,
     >,
     grounds_q: Query<Entity, With<Ground>>,
+    timestep: Res<PhysicsTimestep>,
 ) {
-    for (mut p, mut v, mut a, cs, sprite) in player_q.iter_mut() {
+    for (mut p, mut v, mut a, mut contacts, drop_through, dash_timers, cs) in player_q.iter_mut() {
+        // hazards touched in an earlier sub-step of this tick still count
+        *contacts = Contacts {
+            hazard: contacts.hazard,
+            ..Default::default()
+        };
+        let velocity_y = v.0.y;
+        // overlapping several pieces of ground on the same side should only push out once
+        let mut push = Vec2::ZERO;
         for collision_data in cs.0.iter() {
+            // hazards don't push the player out, the FSM kills it instead. dashing players can't
+            // be hurt
+            if let ColliderType::Hazard = collision_data.collision_type.hit {
+                contacts.hazard |= dash_timers.active == 0;
+                continue;
+            }
+
             if grounds_q.get(collision_data.entity).is_ok() {
-                match (&collision_data.direction, &collision_data.collision_type) {
-                    (
-                        &Collision::Top,
-                        &CollisionType::PlayerHitsGround {
-                            ground_pos,
-                            ground_size,
-                        },
-                    ) => {
-                        v.0.y = 0.0;
-                        a.0.y = 0.0;
-                        p.0.y = ground_pos.y + ground_size.y / 2.0 + sprite.size.y / 2.0;
+                if collision_data.collision_type.hit.is_one_way()
+                    && (drop_through.0 > 0
+                        || !lands_on_one_way(
+                            &collision_data.direction,
+                            collision_data.depth,
+                            velocity_y,
+                            timestep.sub_dt(),
+                        ))
+                {
+                    continue;
+                }
+
+                let correction = collision_data.normal * collision_data.depth;
+                if correction.x.abs() > push.x.abs() {
+                    push.x = correction.x;
+                }
+                if correction.y.abs() > push.y.abs() {
+                    push.y = correction.y;
+                }
+
+                // stop moving into the ground
+                let into_ground = v.0.dot(collision_data.normal);
+                if into_ground < 0.0 {
+                    v.0 -= collision_data.normal * into_ground;
+                }
+
+                match collision_data.direction {
+                    Collision::Top => {
+                        contacts.ground = true;
+                        contacts.ground_entity = Some(collision_data.entity);
+                    }
+                    // a head bonk ends the jump. gravity is kept so the player falls away
+                    Collision::Bottom => {
+                        v.0.y = v.0.y.min(0.0);
+                        contacts.ceiling = true;
+                    }
+                    Collision::Left => {
+                        a.0.x = a.0.x.min(0.0);
+                        contacts.right_wall = true;
+                    }
+                    Collision::Right => {
+                        a.0.x = a.0.x.max(0.0);
+                        contacts.left_wall = true;
                     }
-                    _ => {}
                 }
                 // TODO: remove collision event if it matches
             }
         }
+        p.0 += push;
+    }
+}
+
+// the ground ray hangs from the player's center
+pub fn update_player_ray_position(
+    player_q: Query<&Position, (With<Player>, Without<PlayerRay>)>,
+    mut ray_q: Query<&mut Position, (Or<(With<PlayerRay>, With<PlayerSideRay>)>, Without<Player>)>,
+) {
+    let player_p = player_q.single();
+    for mut ray_p in ray_q.iter_mut() {
+        ray_p.0 = player_p.0;
+    }
+}
+
+// moves the player along with whatever it was standing on last step
+pub fn carry_player_on_platforms(
+    mut player_q: Query<(&mut Position, &Contacts), With<Player>>,
+    platforms_q: Query<&Displacement, (With<MovingPlatform>, Without<Player>)>,
+) {
+    for (mut p, contacts) in player_q.iter_mut() {
+        let platform_displacement = match contacts.ground_entity.map(|e| platforms_q.get(e)) {
+            Some(Ok(platform_displacement)) => platform_displacement,
+            _ => continue,
+        };
+        p.0 += platform_displacement.0;
+    }
+}
+
+// keeps a grounded player on the ground when it drops away a little, like running down a slope,
+// and lets the player fall once it drops away further
+pub fn snap_player_to_ground(
+    mut player_q: Query<
+        (
+            &mut Position,
+            &mut Velocity,
+            &mut Contacts,
+            &Hurtbox,
+            &PlayerFSM,
+            &DropThrough,
+        ),
+        With<Player>,
+    >,
+    ray_q: Query<&Collisions, With<PlayerRay>>,
+    grounds_q: Query<Entity, With<Ground>>,
+    physics_settings: Res<Assets<PhysicsSettings>>,
+    physics_settings_handle: Res<PhysicsSettingsHandle>,
+) {
+    let s = match physics_settings.get(&physics_settings_handle.0) {
+        Some(s) => s,
+        None => return,
+    };
+
+    for (mut p, mut v, mut contacts, hurtbox, fsm, drop_through) in player_q.iter_mut() {
+        // the FSM hasn't run yet this step, so this is a player that was on the ground last step,
+        // hasn't jumped or dropped and isn't touching it now. otherwise the FSM lets it fall
+        if contacts.ground
+            || fsm.0.active_state() != Some(&PlayerState::OnGround)
+            || v.0.y > 0.0
+            || drop_through.0 > 0
+        {
+            continue;
+        }
+
+        let (min, _) = hurtbox.shape.bounds(p.0);
+        if let Some((y, entity)) = ground_below(&ray_q, &grounds_q, false) {
+            if min.y - y <= s.ground_snap_distance {
+                p.0.y -= min.y - y;
+                v.0.y = 0.0;
+                contacts.ground = true;
+                contacts.ground_entity = Some(entity);
+            }
+        }
+    }
+}
+
+// a player the FSM still has on the ground starts falling in the sub-step the ground ray stops
+// finding ground it could snap to, instead of floating until the FSM sees it left at the end of
+// the tick. ground found again before then takes the gravity away
+pub fn fall_off_ledges(
+    mut player_q: Query<
+        (
+            &Position,
+            &Velocity,
+            &mut Acceleration,
+            &Hurtbox,
+            &PlayerFSM,
+            &DropThrough,
+        ),
+        With<Player>,
+    >,
+    ray_q: Query<&Collisions, With<PlayerRay>>,
+    grounds_q: Query<Entity, With<Ground>>,
+    physics_settings: Res<Assets<PhysicsSettings>>,
+    physics_settings_handle: Res<PhysicsSettingsHandle>,
+) {
+    let s = match physics_settings.get(&physics_settings_handle.0) {
+        Some(s) => s,
+        None => return,
+    };
+
+    for (p, v, mut a, hurtbox, fsm, drop_through) in player_q.iter_mut() {
+        // jumps set their own gravity
+        if fsm.0.active_state() != Some(&PlayerState::OnGround) || v.0.y > 0.0 {
+            continue;
+        }
+
+        let (min, _) = hurtbox.shape.bounds(p.0);
+        let grounded = ground_below(&ray_q, &grounds_q, drop_through.0 > 0)
+            .map_or(false, |(y, _)| min.y - y <= s.ground_snap_distance);
+        a.0.y = if grounded { 0.0 } else { s.normal_gravity };
     }
 }
 
@@ -145,16 +763,307 @@ pub fn handle_player_ray_collides_ground(
 ) {
     for (c) in player_rays.iter() {
         for collision_data in c.0.iter() {
-            match (&collision_data.direction, &collision_data.collision_type) {
-                (
-                    &Collision::Top,
-                    &CollisionType::PlayerRayHitsGround {
-                        ground_pos,
-                        ground_size,
-                    },
-                ) => {}
+            match (
+                &collision_data.direction,
+                &collision_data.collision_type.hit,
+            ) {
+                (&Collision::Top, &ColliderType::Ground) => {}
                 _ => {}
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::{CollisionType, PhysicsStage};
+    use crate::testing::{self, actions};
+
+    fn ray_hit(entity: u32, y: f32, hit: ColliderType) -> CollisionData {
+        CollisionData {
+            entity: Entity::new(entity),
+            direction: Collision::Top,
+            depth: 0.0,
+            normal: Vec2::new(0.0, 1.0),
+            point: Vec2::new(0.0, y),
+            collision_type: CollisionType {
+                hit,
+                hit_shape: CollisionShape::Rect(Vec2::new(80.0, 0.0)),
+            },
+        }
+    }
+
+    /*
+          |
+        ====  <- one-way, being dropped through
+          |
+          v
+      ==========
+    */
+    #[test]
+    fn ground_below_skips_one_way_platforms_while_dropping_through() {
+        let hits = vec![
+            ray_hit(0, 70.0, ColliderType::OneWayGround),
+            ray_hit(1, 0.0, ColliderType::Ground),
+        ];
+
+        let standing = highest_ground(hits.iter(), |_| true, false);
+        assert_eq!(standing, Some((70.0, Entity::new(0))));
+
+        let dropping = highest_ground(hits.iter(), |_| true, true);
+        assert_eq!(dropping, Some((0.0, Entity::new(1))));
+    }
+
+    #[test]
+    fn ground_below_finds_nothing_when_only_dropping_through() {
+        let hits = vec![ray_hit(0, 70.0, ColliderType::OneWayGround)];
+        assert_eq!(highest_ground(hits.iter(), |_| true, true), None);
+    }
+
+    // jumps have to come from the ground, so air jumps are turned off
+    fn jump_settings(coyote_ticks: u32, jump_buffer_ticks: u32) -> PhysicsSettings {
+        PhysicsSettings {
+            coyote_ticks,
+            jump_buffer_ticks,
+            air_jumps: 0,
+            ..testing::settings()
+        }
+    }
+
+    // the player at `spawn_point` above a floor with its top at y = 0, after the first update
+    // spawns it. returns the floor
+    fn level(settings: PhysicsSettings, spawn_point: Vec2) -> (App, PhysicsStage, Entity) {
+        let mut app = testing::app(settings);
+        app.insert_resource(SpawnPoint(spawn_point))
+            .add_startup_system(spawn_player);
+        let floor = testing::spawn_floor(&mut app, Vec2::new(0.0, -30.0), Vec2::new(240.0, 60.0));
+        app.update();
+        (app, crate::physics_stage(), floor)
+    }
+
+    // the player standing still on the floor
+    fn standing(settings: PhysicsSettings) -> (App, PhysicsStage, Entity) {
+        let (mut app, mut stage, floor) = level(settings, SpawnPoint::default().0);
+        for _ in 0..10 {
+            testing::step(&mut app, &mut stage, Actions::default());
+        }
+        assert_eq!(state(&mut app), Some(PlayerState::OnGround));
+        (app, stage, floor)
+    }
+
+    fn state(app: &mut App) -> Option<PlayerState> {
+        let player = testing::player(app);
+        app.world
+            .get::<PlayerFSM>(player)
+            .unwrap()
+            .0
+            .active_state()
+            .copied()
+    }
+
+    fn velocity(app: &mut App) -> Vec2 {
+        let player = testing::player(app);
+        app.world.get::<Velocity>(player).unwrap().0
+    }
+
+    fn jump_timers(app: &mut App) -> JumpTimers {
+        let player = testing::player(app);
+        *app.world.get::<JumpTimers>(player).unwrap()
+    }
+
+    fn press_jump() -> Actions {
+        actions(&[Action::Jump], &[Action::Jump])
+    }
+
+    #[test]
+    fn ground_jumps_work_with_coyote_time_and_buffering_turned_off() {
+        let (mut app, mut stage, _) = standing(jump_settings(0, 0));
+        testing::step(&mut app, &mut stage, press_jump());
+        assert!(velocity(&mut app).y > 0.0);
+        assert_eq!(state(&mut app), Some(PlayerState::InAirPressedB));
+    }
+
+    #[test]
+    fn jumps_have_gravity_from_their_first_tick() {
+        let s = testing::settings();
+        let (mut app, mut stage, _) = standing(s.clone());
+        testing::step(&mut app, &mut stage, press_jump());
+        let expected = s.initial_jump_velocity + s.hold_gravity / s.tick_rate;
+        assert!((velocity(&mut app).y - expected).abs() < 1e-3);
+    }
+
+    // with one sub-step the FSM already catches this before the next one
+    #[test]
+    fn players_start_falling_in_the_sub_step_they_leave_the_ground() {
+        let (mut app, mut stage, floor) = standing(PhysicsSettings {
+            sub_steps: 4,
+            ..testing::settings()
+        });
+        app.world.get_mut::<Position>(floor).unwrap().0.y = -1000.0;
+        testing::step(&mut app, &mut stage, Actions::default());
+        assert!(velocity(&mut app).y < 0.0);
+    }
+
+    // the player dies standing on the floor in the middle of a drop and a dash cooldown, and comes
+    // back in the air above it
+    #[test]
+    fn respawning_forgets_everything_from_before_dying() {
+        let s = testing::settings();
+        let (mut app, mut stage, _) = standing(s.clone());
+        app.insert_resource(SpawnPoint(Vec2::new(0.0, 100.0)));
+        let player = testing::player(&mut app);
+        app.world.get_mut::<DropThrough>(player).unwrap().0 = 1000;
+        app.world.get_mut::<DashTimers>(player).unwrap().cooldown = 1000;
+        app.world
+            .get_mut::<PlayerFSM>(player)
+            .unwrap()
+            .0
+            .change_active_state(
+                Some(PlayerState::Dead),
+                &mut PlayerMemory::at_rest(&s),
+                true,
+            )
+            .unwrap();
+
+        for _ in 0..s.respawn_delay_ticks + 2 {
+            testing::step(&mut app, &mut stage, Actions::default());
+            if state(&mut app) != Some(PlayerState::Dead) {
+                break;
+            }
+        }
+        assert_eq!(state(&mut app), Some(PlayerState::OnGround));
+
+        let contacts = *app.world.get::<Contacts>(player).unwrap();
+        assert!(!contacts.ground);
+        assert_eq!(contacts.ground_entity, None);
+        assert_eq!(jump_timers(&mut app).coyote, 0);
+        assert_eq!(app.world.get::<DashTimers>(player).unwrap().cooldown, 0);
+        assert_eq!(app.world.get::<DropThrough>(player).unwrap().0, 0);
+    }
+
+    // jumps are allowed in the tick the ground disappears, then for coyote_ticks more
+    #[test]
+    fn coyote_time_allows_jumps_for_that_many_ticks_after_leaving_the_ground() {
+        for &coyote_ticks in [0, 1, 3].iter() {
+            for late in 0..=coyote_ticks + 1 {
+                let (mut app, mut stage, floor) = standing(jump_settings(coyote_ticks, 0));
+                app.world.get_mut::<Position>(floor).unwrap().0.y = -1000.0;
+                for _ in 0..late {
+                    testing::step(&mut app, &mut stage, Actions::default());
+                }
+                testing::step(&mut app, &mut stage, press_jump());
+
+                assert_eq!(
+                    velocity(&mut app).y > 0.0,
+                    late <= coyote_ticks,
+                    "coyote_ticks {}, pressed {} ticks late",
+                    coyote_ticks,
+                    late
+                );
+            }
+        }
+    }
+
+    // the first tick a jump can come from the ground is the one after landing. a jump pressed up
+    // to jump_buffer_ticks before then still happens in it
+    #[test]
+    fn buffered_jumps_fire_on_landing_within_the_window() {
+        let spawn_point = Vec2::new(0.0, 100.0);
+        let landing = {
+            let (mut app, mut stage, _) = level(jump_settings(0, 0), spawn_point);
+            (0..)
+                .find(|_| {
+                    testing::step(&mut app, &mut stage, Actions::default());
+                    jump_timers(&mut app).coyote > 0
+                })
+                .unwrap()
+        };
+        assert!(landing > 5);
+
+        for &jump_buffer_ticks in [0, 1, 3].iter() {
+            for pressed in landing - jump_buffer_ticks - 1..=landing + 1 {
+                let (mut app, mut stage, _) =
+                    level(jump_settings(0, jump_buffer_ticks), spawn_point);
+                for tick in 0..=landing + 1 {
+                    let held = if tick < pressed {
+                        Actions::default()
+                    } else if tick == pressed {
+                        press_jump()
+                    } else {
+                        actions(&[Action::Jump], &[])
+                    };
+                    testing::step(&mut app, &mut stage, held);
+                }
+
+                assert_eq!(
+                    velocity(&mut app).y > 0.0,
+                    pressed + jump_buffer_ticks > landing,
+                    "jump_buffer_ticks {}, pressed on tick {} of a landing on tick {}",
+                    jump_buffer_ticks,
+                    pressed,
+                    landing
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn air_jumps_fire_in_the_tick_they_are_pressed_without_buffering() {
+        for &jump_buffer_ticks in [0, 1].iter() {
+            let (mut app, mut stage, floor) = standing(PhysicsSettings {
+                air_jumps: 1,
+                ..jump_settings(0, jump_buffer_ticks)
+            });
+            app.world.get_mut::<Position>(floor).unwrap().0.y = -1000.0;
+            for _ in 0..3 {
+                testing::step(&mut app, &mut stage, Actions::default());
+            }
+            testing::step(&mut app, &mut stage, press_jump());
+            assert_eq!(state(&mut app), Some(PlayerState::AirJump));
+        }
+    }
+
+    /*
+         ___ |
+        |   ||
+        |___||
+             |
+    */
+    #[test]
+    fn wall_jumps_fire_in_the_tick_they_are_pressed_without_buffering() {
+        for &jump_buffer_ticks in [0, 1].iter() {
+            let (mut app, mut stage, floor) = standing(jump_settings(0, jump_buffer_ticks));
+            testing::spawn_floor(&mut app, Vec2::new(19.0, 0.0), Vec2::new(6.0, 400.0));
+            app.world.get_mut::<Position>(floor).unwrap().0.y = -1000.0;
+            let hold_right = actions(&[Action::MoveRight], &[]);
+            for _ in 0..3 {
+                testing::step(&mut app, &mut stage, hold_right.clone());
+            }
+            assert_eq!(state(&mut app), Some(PlayerState::WallSlide));
+
+            testing::step(
+                &mut app,
+                &mut stage,
+                actions(&[Action::MoveRight, Action::Jump], &[Action::Jump]),
+            );
+            assert_eq!(state(&mut app), Some(PlayerState::WallJump));
+        }
+    }
+
+    #[test]
+    fn dashes_fire_in_the_tick_they_are_pressed_without_buffering() {
+        for &dash_buffer_ticks in [0, 1].iter() {
+            let (mut app, mut stage, _) = standing(PhysicsSettings {
+                dash_buffer_ticks,
+                ..testing::settings()
+            });
+            testing::step(
+                &mut app,
+                &mut stage,
+                actions(&[Action::Dash], &[Action::Dash]),
+            );
+            assert_eq!(state(&mut app), Some(PlayerState::Dash));
+        }
+    }
+}
@@ -1,11 +1,79 @@
-use crate::physics::{Acceleration, PhysicsSettings};
+use crate::physics::PhysicsSettings;
+use crate::player::{Contacts, DashTimers, JumpTimers};
 use bevy::prelude::*;
 use emergent::prelude::*;
 use std::hash::Hash;
 
-pub struct PlayerMemory<'a> {
-    a: Mut<'a, Acceleration>, // TODO: replace with a system param
-                              // settings: &'static PhysicsSettings,
+// buttons as they were when the FSM ran
+#[derive(Default, Clone, Copy)]
+pub struct PlayerInput {
+    pub left: bool,
+    pub right: bool,
+    pub jump_held: bool,
+}
+
+// the physics settings the states use, copied out of the settings asset so the FSM doesn't clone
+// all of them every update
+#[derive(Clone, Copy)]
+pub struct StateSettings {
+    pub normal_gravity: f32,
+    pub hold_gravity: f32,
+    pub air_jump_velocity: f32,
+    pub max_run_speed: f32,
+    pub wall_slide_speed: f32,
+    pub wall_jump_velocity: Vec2,
+    pub wall_jump_lock_ticks: u32,
+    pub dash_speed: f32,
+    pub dash_ticks: u32,
+    pub dash_cooldown_ticks: u32,
+}
+
+impl From<&PhysicsSettings> for StateSettings {
+    fn from(s: &PhysicsSettings) -> Self {
+        StateSettings {
+            normal_gravity: s.normal_gravity,
+            hold_gravity: s.hold_gravity,
+            air_jump_velocity: s.air_jump_velocity,
+            max_run_speed: s.max_run_speed,
+            wall_slide_speed: s.wall_slide_speed,
+            wall_jump_velocity: s.wall_jump_velocity,
+            wall_jump_lock_ticks: s.wall_jump_lock_ticks,
+            dash_speed: s.dash_speed,
+            dash_ticks: s.dash_ticks,
+            dash_cooldown_ticks: s.dash_cooldown_ticks,
+        }
+    }
+}
+
+// copy of the player's state that the FSM reads and writes, copied back onto the player after
+// each update
+pub struct PlayerMemory {
+    pub v: Vec2,
+    pub a: Vec2,
+    pub settings: StateSettings,
+    pub contacts: Contacts,
+    // side of the player a wall is within reach on, -1 for left and 1 for right
+    pub wall: Option<f32>,
+    pub timers: JumpTimers,
+    pub dash: DashTimers,
+    pub input: PlayerInput,
+}
+
+impl PlayerMemory {
+    // a player that has just been reset, standing still with nothing touched, held or running. the
+    // player's own components already match, so what the FSM does to this can be dropped
+    pub fn at_rest(s: &PhysicsSettings) -> Self {
+        PlayerMemory {
+            v: Vec2::ZERO,
+            a: Vec2::ZERO,
+            settings: s.into(),
+            contacts: Contacts::default(),
+            wall: None,
+            timers: JumpTimers::default(),
+            dash: DashTimers::default(),
+            input: PlayerInput::default(),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
@@ -13,65 +81,317 @@ pub enum PlayerState {
     OnGround,
     InAirPressedB,
     InAirReleasedB,
+    AirJump,
+    WallSlide,
+    WallJump,
+    Dash,
     Dead,
 }
 
 struct OnGroundTasks;
-impl<'a> Task<PlayerMemory<'a>> for OnGroundTasks {
+impl Task<PlayerMemory> for OnGroundTasks {
     fn on_enter(&mut self, memory: &mut PlayerMemory) {
-        memory.a.0.y = 0.0;
+        memory.a.y = 0.0;
+        memory.dash.spent = false;
     }
 }
 
 struct InAirPressedBTasks;
-impl<'a> Task<PlayerMemory<'a>> for InAirPressedBTasks {
+impl Task<PlayerMemory> for InAirPressedBTasks {
     fn on_enter(&mut self, memory: &mut PlayerMemory) {
-        // memory.player_query.0.y = memory.settings.hold_gravity;
-        memory.a.0.y = -1.0;
+        memory.a.y = memory.settings.hold_gravity;
     }
 }
 
 struct InAirReleasedBTasks;
-impl<'a> Task<PlayerMemory<'a>> for InAirReleasedBTasks {
+impl Task<PlayerMemory> for InAirReleasedBTasks {
+    fn on_enter(&mut self, memory: &mut PlayerMemory) {
+        memory.a.y = memory.settings.normal_gravity;
+    }
+}
+
+// like holding B after a ground jump, with its own velocity and one fewer air jump left
+struct AirJumpTasks;
+impl Task<PlayerMemory> for AirJumpTasks {
+    fn on_enter(&mut self, memory: &mut PlayerMemory) {
+        memory.v.y = memory.settings.air_jump_velocity;
+        memory.a.y = memory.settings.hold_gravity;
+        memory.timers.buffer = 0;
+        memory.timers.air_jumps = memory.timers.air_jumps.saturating_sub(1);
+    }
+}
+
+struct WallSlideTasks;
+impl Task<PlayerMemory> for WallSlideTasks {
+    fn on_enter(&mut self, memory: &mut PlayerMemory) {
+        memory.a.y = memory.settings.normal_gravity;
+    }
+
+    fn on_process(&mut self, memory: &mut PlayerMemory) {
+        memory.v.y = memory.v.y.max(-memory.settings.wall_slide_speed);
+    }
+}
+
+// kicked away from the wall with sideways input locked out for a few ticks
+struct WallJumpTasks;
+impl Task<PlayerMemory> for WallJumpTasks {
     fn on_enter(&mut self, memory: &mut PlayerMemory) {
-        // memory.player_query.0.y = memory.settings.normal_gravity;
-        memory.a.0.y = -2.0;
+        let side = memory.wall.unwrap_or(0.0);
+        let kick = memory.settings.wall_jump_velocity;
+        memory.v = Vec2::new(-side * kick.x, kick.y);
+        memory.a = Vec2::new(0.0, memory.settings.hold_gravity);
+        memory.timers.buffer = 0;
+        memory.timers.wall_lock = memory.settings.wall_jump_lock_ticks;
+    }
+
+    fn on_process(&mut self, memory: &mut PlayerMemory) {
+        memory.timers.wall_lock = memory.timers.wall_lock.saturating_sub(1);
+    }
+}
+
+// straight along the input, or the way the player is already moving, without gravity
+struct DashTasks;
+impl Task<PlayerMemory> for DashTasks {
+    fn on_enter(&mut self, memory: &mut PlayerMemory) {
+        let direction = if memory.input.left != memory.input.right {
+            if memory.input.left {
+                -1.0
+            } else {
+                1.0
+            }
+        } else if memory.v.x < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        memory.v = Vec2::new(direction * memory.settings.dash_speed, 0.0);
+        memory.a = Vec2::ZERO;
+        memory.dash.active = memory.settings.dash_ticks;
+        memory.dash.cooldown = memory.settings.dash_cooldown_ticks;
+        memory.dash.buffer = 0;
+        memory.dash.spent = !memory.contacts.ground;
+    }
+
+    fn on_process(&mut self, memory: &mut PlayerMemory) {
+        memory.v.y = 0.0;
+        memory.a = Vec2::ZERO;
+        memory.dash.active = memory.dash.active.saturating_sub(1);
     }
+
+    // back to running speed so the dash doesn't carry on through the air
+    fn on_exit(&mut self, memory: &mut PlayerMemory) {
+        let max = memory.settings.max_run_speed;
+        memory.v.x = memory.v.x.max(-max).min(max);
+        memory.dash.active = 0;
+    }
+}
+
+// frozen in place until the player is respawned
+struct DeadTasks;
+impl Task<PlayerMemory> for DeadTasks {
+    fn on_enter(&mut self, memory: &mut PlayerMemory) {
+        memory.v = Vec2::ZERO;
+        memory.a = Vec2::ZERO;
+    }
+}
+
+fn hit_hazard() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| memory.contacts.hazard)
+}
+
+fn landed() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| memory.contacts.ground)
+}
+
+// only a jump leaves the ground moving up with the button held
+fn jumped() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| {
+        !memory.contacts.ground && memory.input.jump_held && memory.v.y > 0.0
+    })
+}
+
+fn left_ground() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| !memory.contacts.ground)
+}
+
+fn released_b() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| !memory.input.jump_held)
+}
+
+// hitting a ceiling cuts a held jump short the same as letting go of B
+fn bonked() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| memory.contacts.ceiling)
+}
+
+// a jump pressed too late for coyote time uses up an air jump instead
+fn air_jumped() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| {
+        !memory.contacts.ground
+            && memory.timers.coyote == 0
+            && memory.timers.buffer > 0
+            && memory.timers.air_jumps > 0
+    })
+}
+
+fn dashed() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| {
+        memory.dash.buffer > 0 && memory.dash.cooldown == 0 && !memory.dash.spent
+    })
+}
+
+fn dash_over_on_ground() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| memory.dash.active == 0 && memory.contacts.ground)
+}
+
+fn dash_over() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| memory.dash.active == 0)
+}
+
+fn holding_towards_wall(memory: &PlayerMemory) -> bool {
+    match memory.wall {
+        Some(side) if side < 0.0 => memory.input.left,
+        Some(_) => memory.input.right,
+        None => false,
+    }
+}
+
+// falling alongside a wall while pushing into it
+fn wall_slid() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| {
+        !memory.contacts.ground && memory.v.y <= 0.0 && holding_towards_wall(memory)
+    })
+}
+
+fn left_wall() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| !holding_towards_wall(memory))
+}
+
+fn wall_jumped() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| memory.timers.buffer > 0)
+}
+
+fn wall_lock_over() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| memory.timers.wall_lock == 0)
+}
+
+fn wall_lock_over_and_released_b() -> ClosureCondition<PlayerMemory> {
+    ClosureCondition::new(|memory: &PlayerMemory| {
+        memory.timers.wall_lock == 0 && !memory.input.jump_held
+    })
 }
 
 #[derive(Component)]
-pub struct PlayerFSM<'a>(pub Machinery<PlayerMemory<'a>, PlayerState>);
-impl<'a> PlayerFSM<'a> {
+pub struct PlayerFSM(pub Machinery<PlayerMemory, PlayerState>);
+impl PlayerFSM {
     pub fn new() -> Self {
+        // changes are checked in order, so dying wins over everything and jumping wins over
+        // walking off a ledge. respawning forces the change out of Dead
         let machinery = MachineryBuilder::default()
             .state(
                 PlayerState::OnGround,
-                MachineryState::task(NoTask::default())
-                    .change(MachineryChange::new(PlayerState::InAirPressedB, true))
-                    .change(MachineryChange::new(PlayerState::InAirReleasedB, true))
-                    .change(MachineryChange::new(PlayerState::Dead, true)),
+                MachineryState::task(OnGroundTasks)
+                    .change(MachineryChange::new(PlayerState::Dead, hit_hazard()))
+                    .change(MachineryChange::new(PlayerState::Dash, dashed()))
+                    .change(MachineryChange::new(PlayerState::InAirPressedB, jumped()))
+                    .change(MachineryChange::new(
+                        PlayerState::InAirReleasedB,
+                        left_ground(),
+                    )),
             )
             .state(
                 PlayerState::InAirPressedB,
-                MachineryState::task(NoTask::default())
-                    .change(MachineryChange::new(PlayerState::OnGround, true))
-                    .change(MachineryChange::new(PlayerState::InAirReleasedB, true))
-                    .change(MachineryChange::new(PlayerState::Dead, true)),
+                MachineryState::task(InAirPressedBTasks)
+                    .change(MachineryChange::new(PlayerState::Dead, hit_hazard()))
+                    .change(MachineryChange::new(PlayerState::Dash, dashed()))
+                    .change(MachineryChange::new(PlayerState::OnGround, landed()))
+                    .change(MachineryChange::new(PlayerState::WallSlide, wall_slid()))
+                    .change(MachineryChange::new(PlayerState::AirJump, air_jumped()))
+                    .change(MachineryChange::new(PlayerState::InAirReleasedB, bonked()))
+                    .change(MachineryChange::new(
+                        PlayerState::InAirReleasedB,
+                        released_b(),
+                    )),
             )
             .state(
                 PlayerState::InAirReleasedB,
-                MachineryState::task(NoTask::default())
-                    .change(MachineryChange::new(PlayerState::OnGround, true))
-                    .change(MachineryChange::new(PlayerState::InAirPressedB, true))
-                    .change(MachineryChange::new(PlayerState::Dead, true)),
+                MachineryState::task(InAirReleasedBTasks)
+                    .change(MachineryChange::new(PlayerState::Dead, hit_hazard()))
+                    .change(MachineryChange::new(PlayerState::Dash, dashed()))
+                    .change(MachineryChange::new(PlayerState::OnGround, landed()))
+                    .change(MachineryChange::new(PlayerState::WallSlide, wall_slid()))
+                    .change(MachineryChange::new(PlayerState::AirJump, air_jumped()))
+                    // jumping during coyote time after walking off a ledge
+                    .change(MachineryChange::new(PlayerState::InAirPressedB, jumped())),
+            )
+            .state(
+                PlayerState::AirJump,
+                MachineryState::task(AirJumpTasks)
+                    .change(MachineryChange::new(PlayerState::Dead, hit_hazard()))
+                    .change(MachineryChange::new(PlayerState::Dash, dashed()))
+                    .change(MachineryChange::new(PlayerState::OnGround, landed()))
+                    .change(MachineryChange::new(PlayerState::WallSlide, wall_slid()))
+                    .change(MachineryChange::new(PlayerState::InAirReleasedB, bonked()))
+                    .change(MachineryChange::new(
+                        PlayerState::InAirReleasedB,
+                        released_b(),
+                    )),
+            )
+            .state(
+                PlayerState::WallSlide,
+                MachineryState::task(WallSlideTasks)
+                    .change(MachineryChange::new(PlayerState::Dead, hit_hazard()))
+                    .change(MachineryChange::new(PlayerState::Dash, dashed()))
+                    .change(MachineryChange::new(PlayerState::OnGround, landed()))
+                    .change(MachineryChange::new(PlayerState::WallJump, wall_jumped()))
+                    .change(MachineryChange::new(
+                        PlayerState::InAirReleasedB,
+                        left_wall(),
+                    )),
+            )
+            .state(
+                PlayerState::WallJump,
+                MachineryState::task(WallJumpTasks)
+                    .change(MachineryChange::new(PlayerState::Dead, hit_hazard()))
+                    .change(MachineryChange::new(PlayerState::Dash, dashed()))
+                    .change(MachineryChange::new(PlayerState::OnGround, landed()))
+                    .change(MachineryChange::new(PlayerState::AirJump, air_jumped()))
+                    .change(MachineryChange::new(
+                        PlayerState::InAirReleasedB,
+                        wall_lock_over_and_released_b(),
+                    ))
+                    .change(MachineryChange::new(
+                        PlayerState::InAirPressedB,
+                        wall_lock_over(),
+                    )),
+            )
+            .state(
+                PlayerState::Dash,
+                MachineryState::task(DashTasks)
+                    .change(MachineryChange::new(PlayerState::Dead, hit_hazard()))
+                    .change(MachineryChange::new(
+                        PlayerState::OnGround,
+                        dash_over_on_ground(),
+                    ))
+                    .change(MachineryChange::new(
+                        PlayerState::InAirReleasedB,
+                        dash_over(),
+                    )),
             )
             .state(
                 PlayerState::Dead,
-                MachineryState::task(NoTask::default())
-                    .change(MachineryChange::new(PlayerState::OnGround, true)),
+                MachineryState::task(DeadTasks)
+                    .change(MachineryChange::new(PlayerState::OnGround, false)),
             )
             .build();
 
         PlayerFSM(machinery)
     }
+
+    pub fn is_dead(self: &Self) -> bool {
+        self.0.active_state() == Some(&PlayerState::Dead)
+    }
+
+    pub fn is_dashing(self: &Self) -> bool {
+        self.0.active_state() == Some(&PlayerState::Dash)
+    }
 }
@@ -0,0 +1,312 @@
+use crate::checkpoint::{Checkpoint, CheckpointMaterials};
+use crate::ground::MovingPlatform;
+use crate::input::Actions;
+use crate::physics::{teleport, Position, PreviousPosition, Velocity};
+use crate::player::{Player, PlayerReset, Respawn, SpawnPoint};
+use crate::player_fsm::PlayerFSM;
+use bevy::prelude::*;
+
+const REPLAY_PATH: &str = "last.replay.ron";
+
+// the actions seen by every physics step since recording started, and the state of the level when
+// it started. moving platforms always start from the beginning of their paths
+#[derive(serde::Serialize, serde::Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct Recording {
+    pub start: Vec2,
+    pub spawn_point: Vec2,
+    // checkpoints are told apart by where they respawn the player
+    pub activated_checkpoints: Vec<Vec2>,
+    pub ticks: Vec<Actions>,
+}
+
+impl Recording {
+    pub fn to_ron(self: &Self) -> Result<String, ron::Error> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+    }
+
+    pub fn from_ron(s: &str) -> Result<Self, ron::Error> {
+        ron::de::from_str(s)
+    }
+}
+
+pub enum ReplayMode {
+    Off,
+    Recording,
+    // index of the next step to feed back
+    Replaying(usize),
+}
+
+impl Default for ReplayMode {
+    fn default() -> Self {
+        ReplayMode::Off
+    }
+}
+
+#[derive(Default)]
+pub struct Replay {
+    pub mode: ReplayMode,
+    pub recording: Recording,
+    // put the player back at the start before the next physics step
+    restart: bool,
+}
+
+impl Replay {
+    // `recording` holds the starting state, its ticks are filled in as the game runs
+    pub fn start_recording(self: &mut Self, recording: Recording) {
+        self.recording = recording;
+        self.mode = ReplayMode::Recording;
+        self.restart = true;
+    }
+
+    pub fn start_replaying(self: &mut Self, recording: Recording) {
+        self.recording = recording;
+        self.mode = ReplayMode::Replaying(0);
+        self.restart = true;
+    }
+}
+
+// F5 starts and stops recording to last.replay.ron, F6 plays it back
+pub fn toggle_replay(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut replay: ResMut<Replay>,
+    player_q: Query<&Position, With<Player>>,
+    checkpoints_q: Query<&Checkpoint>,
+    spawn_point: Res<SpawnPoint>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        if let ReplayMode::Recording = replay.mode {
+            replay.mode = ReplayMode::Off;
+            match replay.recording.to_ron() {
+                Ok(s) => {
+                    if let Err(e) = std::fs::write(REPLAY_PATH, s) {
+                        error!("couldn't save {}: {}", REPLAY_PATH, e);
+                    }
+                }
+                Err(e) => error!("couldn't serialize the recording: {}", e),
+            }
+        } else {
+            replay.start_recording(Recording {
+                start: player_q.single().0,
+                spawn_point: spawn_point.0,
+                activated_checkpoints: checkpoints_q
+                    .iter()
+                    .filter(|checkpoint| checkpoint.activated)
+                    .map(|checkpoint| checkpoint.spawn)
+                    .collect(),
+                ticks: Vec::new(),
+            });
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::F6) {
+        let recording = std::fs::read_to_string(REPLAY_PATH)
+            .map_err(|e| e.to_string())
+            .and_then(|s| Recording::from_ron(&s).map_err(|e| e.to_string()));
+        match recording {
+            Ok(recording) => replay.start_replaying(recording),
+            Err(e) => error!("couldn't load {}: {}", REPLAY_PATH, e),
+        }
+    }
+}
+
+// runs first in each physics step so the rest of the step only ever sees recorded actions
+pub fn record_or_replay_actions(mut actions: ResMut<Actions>, mut replay: ResMut<Replay>) {
+    let replay = &mut *replay;
+    match replay.mode {
+        ReplayMode::Off => {}
+        ReplayMode::Recording => replay.recording.ticks.push(actions.clone()),
+        ReplayMode::Replaying(tick) => match replay.recording.ticks.get(tick) {
+            Some(recorded) => {
+                *actions = recorded.clone();
+                replay.mode = ReplayMode::Replaying(tick + 1);
+            }
+            None => replay.mode = ReplayMode::Off,
+        },
+    }
+}
+
+// both recording and replaying start from a standing start at the recorded position, with the
+// recorded checkpoints and platforms back at the start of their paths. the FSM is replaced so it
+// picks its state again from scratch
+pub fn restart_player(
+    mut replay: ResMut<Replay>,
+    mut player_q: Query<(Entity, &mut PlayerFSM, &mut Respawn, &mut Transform), With<Player>>,
+    mut reset: PlayerReset,
+    mut platforms_q: Query<
+        (
+            &mut MovingPlatform,
+            &mut Position,
+            &mut PreviousPosition,
+            &mut Velocity,
+        ),
+        Without<Player>,
+    >,
+    mut checkpoints_q: Query<(&mut Checkpoint, &mut Handle<ColorMaterial>)>,
+    checkpoint_materials: Res<CheckpointMaterials>,
+    mut spawn_point: ResMut<SpawnPoint>,
+) {
+    if !replay.restart {
+        return;
+    }
+    replay.restart = false;
+
+    spawn_point.0 = replay.recording.spawn_point;
+    for (mut checkpoint, mut material) in checkpoints_q.iter_mut() {
+        checkpoint.activated = replay
+            .recording
+            .activated_checkpoints
+            .contains(&checkpoint.spawn);
+        *material = if checkpoint.activated {
+            checkpoint_materials.active.clone()
+        } else {
+            checkpoint_materials.inactive.clone()
+        };
+    }
+
+    for (mut platform, mut p, mut previous, mut v) in platforms_q.iter_mut() {
+        let start = platform.rewind();
+        teleport(&mut p, &mut previous, start);
+        v.0 = Vec2::ZERO;
+    }
+
+    for (entity, mut fsm, mut respawn, mut t) in player_q.iter_mut() {
+        *fsm = PlayerFSM::new();
+        respawn.0 = None;
+        t.scale = Vec3::ONE;
+        reset.reset(entity, replay.recording.start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::spawn_checkpoints;
+    use crate::ground::{
+        spawn_ground, spawn_hazards, spawn_moving_platforms, MovingPlatforms, MovingPlatformsHandle,
+    };
+    use crate::input::Action;
+    use crate::player::spawn_player;
+    use crate::testing::{self, actions};
+
+    // jump right, holding jump for 10 steps, then keep running right until well after landing
+    fn live_actions(tick: usize) -> Actions {
+        match tick {
+            0 => actions(&[Action::Jump, Action::MoveRight], &[Action::Jump]),
+            1..=9 => actions(&[Action::Jump, Action::MoveRight], &[]),
+            _ => actions(&[Action::MoveRight], &[]),
+        }
+    }
+
+    // the level as the game starts it
+    fn app() -> App {
+        let mut app = testing::app(testing::settings());
+        app.add_startup_system(spawn_player)
+            .add_startup_system(spawn_ground)
+            .add_startup_system(spawn_hazards)
+            .add_startup_system(spawn_checkpoints)
+            .add_system(spawn_moving_platforms);
+
+        let platforms = app
+            .world
+            .get_resource_mut::<Assets<MovingPlatforms>>()
+            .unwrap()
+            .add(ron::de::from_str(include_str!("../assets/level.platforms.ron")).unwrap());
+        app.insert_resource(MovingPlatformsHandle(platforms));
+
+        // the first update spawns the level and sends the platform asset event, the second spawns
+        // the platforms
+        app.update();
+        app.update();
+        app
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Snapshot {
+        player: Vec2,
+        platforms: Vec<Vec2>,
+        spawn_point: Vec2,
+        activated_checkpoints: Vec<Vec2>,
+    }
+
+    fn snapshot(app: &mut App) -> Snapshot {
+        let world = &mut app.world;
+        Snapshot {
+            player: world
+                .query_filtered::<&Position, With<Player>>()
+                .single(world)
+                .0,
+            platforms: world
+                .query_filtered::<&Position, With<MovingPlatform>>()
+                .iter(world)
+                .map(|p| p.0)
+                .collect(),
+            spawn_point: world.get_resource::<SpawnPoint>().unwrap().0,
+            activated_checkpoints: world
+                .query::<&Checkpoint>()
+                .iter(world)
+                .filter(|checkpoint| checkpoint.activated)
+                .map(|checkpoint| checkpoint.spawn)
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn recording_survives_saving_and_loading() {
+        let recording = Recording {
+            start: Vec2::new(-60.0, 15.0),
+            spawn_point: Vec2::new(-60.0, 15.0),
+            activated_checkpoints: vec![Vec2::new(-60.0, 15.0)],
+            ticks: (0..20).map(live_actions).collect(),
+        };
+        let loaded = Recording::from_ron(&recording.to_ron().unwrap()).unwrap();
+        assert_eq!(loaded, recording);
+    }
+
+    #[test]
+    fn replay_ends_where_the_recording_did() {
+        let mut app = app();
+        let mut stage = crate::physics_stage();
+        let spawned = snapshot(&mut app);
+        assert_eq!(spawned.platforms.len(), 2);
+
+        // run left through the first checkpoint, so the platforms are partway along their paths
+        // and the spawn point has moved when recording starts
+        for _ in 0..45 {
+            testing::step(&mut app, &mut stage, actions(&[Action::MoveLeft], &[]));
+        }
+        let start = snapshot(&mut app);
+        assert_ne!(start.platforms, spawned.platforms);
+        assert_ne!(start.spawn_point, spawned.spawn_point);
+
+        app.world
+            .get_resource_mut::<Replay>()
+            .unwrap()
+            .start_recording(Recording {
+                start: start.player,
+                spawn_point: start.spawn_point,
+                activated_checkpoints: start.activated_checkpoints.clone(),
+                ticks: Vec::new(),
+            });
+        for tick in 0..90 {
+            testing::step(&mut app, &mut stage, live_actions(tick));
+        }
+        let recorded = snapshot(&mut app);
+
+        {
+            let mut replay = app.world.get_resource_mut::<Replay>().unwrap();
+            replay.mode = ReplayMode::Off;
+            let loaded = Recording::from_ron(&replay.recording.to_ron().unwrap()).unwrap();
+            assert_eq!(loaded.ticks.len(), 90);
+            replay.start_replaying(loaded);
+        }
+        // the live input is ignored while replaying
+        for _ in 0..90 {
+            testing::step(&mut app, &mut stage, Actions::default());
+        }
+        let replayed = snapshot(&mut app);
+
+        // bit identical, not just close
+        assert_eq!(replayed, recorded);
+        assert!(recorded.player.x > start.player.x);
+    }
+}
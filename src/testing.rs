@@ -0,0 +1,90 @@
+use crate::checkpoint::CheckpointMaterials;
+use crate::ground::{Ground, MovingPlatforms};
+use crate::input::{Action, Actions};
+use crate::physics::{
+    ColliderType, CollisionMatrix, CollisionMatrixHandle, CollisionShape, Hitbox, PhysicsSettings,
+    PhysicsSettingsHandle, PhysicsStage, PhysicsTimestep, Position, SpatialHash,
+};
+use crate::player::{Player, SpawnPoint};
+use crate::replay::Replay;
+use bevy::asset::AssetPlugin;
+use bevy::prelude::*;
+
+// the physics settings the game ships with, read straight from the asset instead of going through
+// the loader
+pub fn settings() -> PhysicsSettings {
+    ron::de::from_str(include_str!("../assets/settings.physics.ron")).unwrap()
+}
+
+// a headless game to step the physics stage in, with `settings` and the shipped collision matrix
+// already loaded. nothing is spawned until the first update
+pub fn app(settings: PhysicsSettings) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin::default())
+        .add_asset::<PhysicsSettings>()
+        .add_asset::<CollisionMatrix>()
+        .add_asset::<MovingPlatforms>()
+        .add_asset::<ColorMaterial>()
+        .init_resource::<SpatialHash>()
+        .init_resource::<SpawnPoint>()
+        .init_resource::<Actions>()
+        .init_resource::<Replay>()
+        .insert_resource(CheckpointMaterials {
+            inactive: Handle::default(),
+            active: Handle::default(),
+        })
+        .insert_resource(PhysicsTimestep {
+            dt: 1.0 / settings.tick_rate,
+            sub_steps: settings.sub_steps,
+            ..Default::default()
+        });
+
+    let settings = app
+        .world
+        .get_resource_mut::<Assets<PhysicsSettings>>()
+        .unwrap()
+        .add(settings);
+    app.insert_resource(PhysicsSettingsHandle(settings));
+
+    let matrix = app
+        .world
+        .get_resource_mut::<Assets<CollisionMatrix>>()
+        .unwrap()
+        .add(ron::de::from_str(include_str!("../assets/settings.collision.ron")).unwrap());
+    app.insert_resource(CollisionMatrixHandle(matrix));
+    app
+}
+
+pub fn actions(pressed: &[Action], just_pressed: &[Action]) -> Actions {
+    let held = |action: Action| if pressed.contains(&action) { 1.0 } else { 0.0 };
+    Actions {
+        pressed: pressed.iter().cloned().collect(),
+        just_pressed: just_pressed.iter().cloned().collect(),
+        move_x: held(Action::MoveRight) - held(Action::MoveLeft),
+    }
+}
+
+// one tick of the physics stage with `actions` held
+pub fn step(app: &mut App, stage: &mut PhysicsStage, actions: Actions) {
+    *app.world.get_resource_mut::<Actions>().unwrap() = actions;
+    stage.step(&mut app.world);
+}
+
+pub fn spawn_floor(app: &mut App, position: Vec2, size: Vec2) -> Entity {
+    app.world
+        .spawn()
+        .insert(Ground)
+        .insert(Position(position))
+        .insert(Hitbox {
+            shape: CollisionShape::Rect(size),
+            col_type: ColliderType::Ground,
+        })
+        .id()
+}
+
+pub fn player(app: &mut App) -> Entity {
+    app.world
+        .query_filtered::<Entity, With<Player>>()
+        .single(&app.world)
+}